@@ -0,0 +1,237 @@
+//! A client for the request/response protocol `server::Server` speaks.
+//!
+//! Signing requests (generating a fresh nonce, hashing it with the key, and attaching the
+//! `Nonce`/`Secret`/`Version` headers) is exactly the logic `server::Request::new` validates on
+//! the way in--this module is the other half, so callers don't have to hand-roll it (the way the
+//! server's own tests do with a mock client).
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::server::{
+    ConfigError, DurationExt, Key, Method, Response, ResponseContent, PROTOCOL_VERSION,
+};
+
+/// A builder for a `Client`, mirroring `server::Config`'s style.
+///
+/// ### Required attributes
+///  - `port`
+///  - `key`
+///
+/// ### Default attributes & values
+///  - `addr` defaults to `127.0.0.1` (localhost)
+#[allow(dead_code)]
+#[derive(Debug, Default, Clone)]
+pub struct ClientConfig {
+    addr: Option<Ipv4Addr>,
+    port: Option<u16>,
+    key: Option<Key>,
+}
+
+impl ClientConfig {
+    /// Creates a new, empty `ClientConfig`.
+    #[must_use]
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the address of the server to connect to.
+    #[allow(dead_code)]
+    pub fn with_addr(mut self, addr: Ipv4Addr) -> Self {
+        self.addr = Some(addr);
+        self
+    }
+
+    /// Sets the address of the server to connect to, to localhost (`127.0.0.1`).
+    #[allow(dead_code)]
+    pub fn on_localhost(self) -> Self {
+        self.with_addr(Ipv4Addr::new(127, 0, 0, 1))
+    }
+
+    /// Sets the port of the server to connect to.
+    #[allow(dead_code)]
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Sets the key used to sign requests.
+    #[allow(dead_code)]
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Builds this `ClientConfig` into a `Client`.
+    ///
+    /// ### Required attributes
+    ///  - `port`
+    ///  - `key`
+    ///
+    /// ### Default attributes & values
+    ///  - `addr` defaults to `127.0.0.1`
+    #[allow(dead_code)]
+    pub fn build(self) -> Result<Client, ConfigError> {
+        let addr = self.addr.unwrap_or(Ipv4Addr::new(127, 0, 0, 1));
+        let port = self.port.ok_or(ConfigError::MissingRequired("port"))?;
+        let key = self.key.ok_or(ConfigError::MissingRequired("key"))?;
+
+        Ok(Client {
+            addr: SocketAddrV4::new(addr, port),
+            key,
+            last_nonce: Mutex::new(0),
+        })
+    }
+}
+
+/// A client for a `server::Server`.
+///
+/// Opens a new connection and signs a fresh request for every call to `get`/`post`--there's no
+/// persistent connection or session state beyond the monotonically increasing nonce.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct Client {
+    addr: SocketAddrV4,
+    key: Key,
+    /// The last nonce used to sign a request, so the next one is guaranteed to be greater (the
+    /// server rejects non-increasing nonces, see `NonceError::FromPast`).
+    last_nonce: Mutex<u128>,
+}
+
+impl Client {
+    /// Creates a `ClientConfig` to build a `Client` with.
+    #[must_use]
+    #[allow(dead_code)]
+    pub fn builder() -> ClientConfig {
+        ClientConfig::new()
+    }
+
+    /// Sends a signed `GET` request to `path` and parses the response.
+    #[allow(dead_code)]
+    pub fn get(&self, path: &str) -> io::Result<Response> {
+        self.request(Method::Get, path, &[])
+    }
+
+    /// Sends a signed `POST` request to `path` with `body` and parses the response.
+    #[allow(dead_code)]
+    pub fn post(&self, path: &str, body: &[u8]) -> io::Result<Response> {
+        self.request(Method::Post, path, body)
+    }
+
+    /// A nonce strictly greater than the last one used, so the server never rejects it as stale.
+    fn next_nonce(&self) -> u128 {
+        let now = Duration::since_unix_epoch().as_millis();
+        let mut last_nonce = self.last_nonce.lock().unwrap();
+        let nonce = now.max(*last_nonce + 1);
+        *last_nonce = nonce;
+        nonce
+    }
+
+    /// Signs and sends a request, then reads and parses the response.
+    fn request(&self, method: Method, path: &str, body: &[u8]) -> io::Result<Response> {
+        let nonce = self.next_nonce();
+        let secret = self.key.generate_secret(nonce);
+
+        let mut stream = TcpStream::connect(self.addr)?;
+        let head = format!(
+            "{method} {path} HTTP/1.1\r\nContent-Length: {}\r\nVersion: {PROTOCOL_VERSION}\r\n\
+             Nonce: {nonce}\r\nSecret: {secret}\r\nConnection: close\r\n\r\n",
+            body.len(),
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(body)?;
+
+        // `Connection: close` above means the server closes the connection once it's written its
+        // response (see `handle_connection`), so reading to completion gets us the whole thing
+        // without having to consult `Content-Length` ourselves.
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw)?;
+        parse_response(&raw)
+    }
+}
+
+/// Parses a raw http response (status line, headers, and body) back into a `Response`.
+///
+/// Understands the headers `Response::write_to` can produce: `Content-Type` (to pick the right
+/// `ResponseContent` variant) and `Transfer-Encoding: chunked` (to de-chunk a streamed body). It
+/// doesn't understand `Content-Encoding`--`Client` never sends an `Accept-Encoding` header, so the
+/// server never compresses a response sent to it.
+fn parse_response(raw: &[u8]) -> io::Result<Response> {
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid_data("malformed http response: no end of headers found"))?;
+    let head = std::str::from_utf8(&raw[..header_end])
+        .map_err(|_| invalid_data("malformed http response: headers aren't valid utf-8"))?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_data("malformed http response: no status line found"))?;
+
+    let headers: Vec<_> = lines.filter_map(|line| line.split_once(": ")).collect();
+    let content_type = headers
+        .iter()
+        .find(|(k, _)| *k == "Content-Type")
+        .map(|(_, v)| *v);
+    let chunked = headers
+        .iter()
+        .any(|(k, v)| *k == "Transfer-Encoding" && v.eq_ignore_ascii_case("chunked"));
+
+    let raw_body = &raw[header_end + 4..];
+    let body = if chunked {
+        dechunk(raw_body)?
+    } else {
+        raw_body.to_owned()
+    };
+
+    let content = match content_type {
+        _ if body.is_empty() => ResponseContent::None,
+        Some("image/png") => ResponseContent::Png(body),
+        Some("image/jpeg") => ResponseContent::Jpeg(body),
+        _ => ResponseContent::Text(String::from_utf8_lossy(&body).into_owned()),
+    };
+
+    Ok(Response::from_parts(status, content))
+}
+
+/// Builds an `io::Error` of kind `InvalidData` for a malformed-response message.
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body (`<hex-len>\r\n<bytes>\r\n`, terminated by a
+/// zero-length chunk) into the concatenated chunk bytes.
+fn dechunk(mut body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| invalid_data("malformed chunk: no chunk-size line found"))?;
+        let size_str = std::str::from_utf8(&body[..line_end])
+            .map_err(|_| invalid_data("malformed chunk: chunk size isn't valid utf-8"))?;
+        let size = usize::from_str_radix(size_str.trim(), 16)
+            .map_err(|_| invalid_data("malformed chunk: chunk size isn't valid hex"))?;
+        body = &body[line_end + 2..];
+
+        if size == 0 {
+            break;
+        }
+
+        let chunk_end = size
+            .checked_add(2)
+            .filter(|&n| n <= body.len())
+            .ok_or_else(|| invalid_data("malformed chunk: chunk size exceeds remaining body"))?;
+
+        out.extend_from_slice(&body[..size]);
+        body = &body[chunk_end..];
+    }
+
+    Ok(out)
+}
@@ -1,6 +1,9 @@
 #![cfg_attr(feature = "no_term", windows_subsystem = "windows")]
+mod client;
 mod server;
+mod service;
 mod util;
+mod window;
 
 use std::error;
 use std::fmt::Display;
@@ -23,6 +26,12 @@ fn main() {
 
 /// Run the server.
 fn run() -> Result<(), Box<dyn error::Error>> {
+    match std::env::args().nth(1).as_deref() {
+        Some("--install") => return service::install().map_err(Into::into),
+        Some("--uninstall") => return service::uninstall().map_err(Into::into),
+        _ => {}
+    }
+
     // We use a dummy logger on release builds.
     #[allow(unreachable_code)]
     #[cfg(debug_assertions)]
@@ -30,38 +39,83 @@ fn run() -> Result<(), Box<dyn error::Error>> {
     #[cfg(not(debug_assertions))]
     let logger = ();
 
+    let pump = window::spawn(logger)?;
+
     // TODO: clean up the results and errors here
-    Server::from_config_file(logger)?
-        .run(|r| match r.path.as_str() {
-            "/minimize" => util::minimize_windows()
-                .inspect_err(|e| logger.server_error(&format!("Failed to minimize windows: {e}")))
-                .to_status_response(500),
+    let server = Server::from_config_file(logger)?;
+    service::run(server, move |r| match r.path.as_str() {
+        "/minimize" => util::minimize_windows(&pump)
+            .inspect_err(|e| logger.server_error(&format!("Failed to minimize windows: {e}")))
+            .to_status_response(500),
 
-            "/lock_screen" => util::lock_the_screen()
-                .inspect_err(|e| logger.server_error(e))
-                .to_status_response(500),
+        "/lock_screen" => util::lock_the_screen()
+            .inspect_err(|e| logger.server_error(e))
+            .to_status_response(500),
 
-            "/ping" => Response::from_status(200),
+        "/ping" => Response::from_status(200),
 
-            "/sleep" => {
-                util::sleep_computer();
-                Response::from_status(200)
-            }
+        "/sleep" => {
+            util::sleep_computer();
+            Response::from_status(200)
+        }
 
-            "/sleep_display" => util::sleep_display()
-                .inspect_err(|e| {
-                    logger.server_error(&format!("Failed to sleep display: {e}"));
-                })
-                .to_status_response(500),
+        "/sleep_display" => util::sleep_display(&pump)
+            .inspect_err(|e| {
+                logger.server_error(&format!("Failed to sleep display: {e}"));
+            })
+            .to_status_response(500),
+
+        "/screenshot" => util::take_screenshot().into_response(Response::from_png),
+
+        "/stream" => Response::from_stream(
+            200,
+            format!("Content-Type: multipart/x-mixed-replace; boundary={}\r\n", util::STREAM_BOUNDARY),
+            util::stream_screen(15, 75),
+        ),
+
+        "/camera" => util::capture_camera(75).into_response(Response::from_jpeg),
+
+        "/camera_stream" => util::stream_camera(75).into_response(|body| {
+            Response::from_stream(
+                200,
+                format!(
+                    "Content-Type: multipart/x-mixed-replace; boundary={}\r\n",
+                    util::STREAM_BOUNDARY
+                ),
+                body,
+            )
+        }),
+
+        "/run" => {
+            let (working_dir, timeout_ms, command_line) = util::parse_run_request(&r.body);
+            util::run_process(&command_line, working_dir.as_deref(), timeout_ms).into_response(
+                |outcome| match outcome {
+                    util::RunOutcome::Exited { exit_code, stdout, stderr } => Response::from_message(
+                        200,
+                        format!(
+                            "exit code: {exit_code}\n--- stdout ---\n{}\n--- stderr ---\n{}",
+                            String::from_utf8_lossy(&stdout),
+                            String::from_utf8_lossy(&stderr),
+                        ),
+                    ),
+                    util::RunOutcome::StillRunning { pid } => {
+                        Response::from_message(202, format!("still running, pid {pid}"))
+                    }
+                },
+            )
+        }
 
-            "/screenshot" => util::take_screenshot().into_response(Response::from_png),
+        "/kill" => match String::from_utf8_lossy(&r.body).trim().parse::<u32>() {
+            Ok(pid) => util::kill_process(pid).into_response(|()| Response::from_status(200)),
+            Err(_) => Response::from_message(400, "invalid pid".to_owned()),
+        },
 
-            other => {
-                logger.connection_refused(&format!("Invalid endpoint requested: \"{other}\""));
-                Response::from_status(404)
-            }
-        })
-        .map_err(Into::into)
+        other => {
+            logger.connection_refused(&format!("Invalid endpoint requested: \"{other}\""));
+            Response::from_status(404)
+        }
+    })
+    .map_err(Into::into)
 }
 
 // We allow dead code because there'll be warnings when compiling in release mode otherwise.
@@ -84,28 +138,21 @@ impl DebugLogger {
 }
 
 impl server::Logger for DebugLogger {
-    fn started_listening(&self, sock_addr: std::net::SocketAddrV4) {
-        Self::print(
-            "started listening",
-            &format!("on {}", sock_addr),
-            Color::BrightGreen,
-        );
-    }
-    fn got_connection(&self, from: std::net::SocketAddr, to_path: &str) {
-        Self::print(
-            "got connection",
-            &format!("from {from} to {to_path}"),
-            Color::Blue,
-        );
-    }
-    fn info(&self, msg: &impl Display) {
-        Self::print("info", msg, Color::Blue);
-    }
-    fn connection_refused(&self, msg: &impl Display) {
-        Self::print("connection refused", msg, Color::Red);
-    }
-    fn server_error(&self, msg: &impl Display) {
-        Self::print("server error", msg, Color::BrightRed);
+    fn log_event(&self, event: server::LogEvent) {
+        match event {
+            server::LogEvent::Info(msg) => Self::print("info", msg, Color::Blue),
+            server::LogEvent::ConnectionAccepted { peer, path } => Self::print(
+                "got connection",
+                &format!("from {peer} to {path}"),
+                Color::Blue,
+            ),
+            server::LogEvent::ConnectionRefused { reason } => {
+                Self::print("connection refused", reason, Color::Red);
+            }
+            server::LogEvent::ServerError { message } => {
+                Self::print("server error", message, Color::BrightRed);
+            }
+        }
     }
 }
 
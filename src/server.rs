@@ -7,6 +7,9 @@ use std::fmt;
 use std::io::{self, Read, Write};
 use std::net::{Ipv4Addr, Shutdown, SocketAddrV4, TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Mutex};
+use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
@@ -14,7 +17,17 @@ use sha2::{Digest, Sha512};
 
 // TODO: add more granular logging
 
-trait DurationExt {
+/// The protocol version this build of the crate speaks.
+///
+/// Clients send this in a `Version` header on every request; the server rejects requests from a
+/// version it doesn't support with a 426 status (see `RequestError::UnsupportedVersion`) rather
+/// than failing in some less obvious way.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The inclusive range of client protocol versions this server accepts.
+const SUPPORTED_PROTOCOL_VERSIONS: std::ops::RangeInclusive<u32> = 1..=PROTOCOL_VERSION;
+
+pub(crate) trait DurationExt {
     /// The amount of time elapsed since the unix epoch.
     fn since_unix_epoch() -> Self;
 }
@@ -68,14 +81,57 @@ impl<T, E: Into<Response>> MapResponse<T> for Result<T, E> {
     }
 }
 
+/// A structured server event, passed to `Logger::log_event`.
+///
+/// This carries the individual fields (a peer address, a request path) that the server already
+/// has on hand when logging, rather than flattening them into prose up front--a logger that wants
+/// structured output (see `JsonLogger`) can use them directly instead of scraping them back out of
+/// a formatted string. Refusals and errors carry their reason as a single message string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogEvent<'a> {
+    /// General information that doesn't fit a more specific variant, such as the server starting
+    /// to listen.
+    Info(&'a str),
+    /// A connection was accepted, validated, and is about to be handled.
+    ConnectionAccepted {
+        /// The address of the client.
+        peer: std::net::SocketAddr,
+        /// The path the request was made to.
+        path: &'a str,
+    },
+    /// A connection was closed outside of normal circumstance, such as for an invalid key.
+    ConnectionRefused {
+        /// Why the connection was refused.
+        reason: &'a str,
+    },
+    /// An internal (server) error occurred.
+    ServerError {
+        /// A message describing the error.
+        message: &'a str,
+    },
+}
+
 /// A trait to be implemented by loggers to log server events.
+///
+/// `log_event` is the only method you need to implement; `info`, `connection_refused`, and
+/// `server_error` are default adapters on top of it for loggers that just want prose and don't
+/// care about `LogEvent`'s structured fields.
 pub trait Logger: fmt::Debug {
+    /// Logs a structured event.
+    fn log_event(&self, event: LogEvent);
+
     /// Logs general information about the server such as listening on a port.
-    fn info(&self, msg: &str);
+    fn info(&self, msg: &str) {
+        self.log_event(LogEvent::Info(msg));
+    }
     /// Logs that a connection was closed outside of normal circumstance, such as for an invalid key.
-    fn connection_refused(&self, msg: &str);
+    fn connection_refused(&self, msg: &str) {
+        self.log_event(LogEvent::ConnectionRefused { reason: msg });
+    }
     /// Logs an internal (server) error.
-    fn server_error(&self, msg: &str);
+    fn server_error(&self, msg: &str) {
+        self.log_event(LogEvent::ServerError { message: msg });
+    }
 }
 
 /// A dummy logger for `server::Server` which does nothing and drops all logs.
@@ -90,9 +146,61 @@ impl DummyLogger {
     }
 }
 impl Logger for DummyLogger {
-    fn info(&self, _: &str) {}
-    fn connection_refused(&self, _: &str) {}
-    fn server_error(&self, _: &str) {}
+    fn log_event(&self, _: LogEvent) {}
+}
+
+/// A logger that emits one JSON object per line to stdout, for piping the server's activity into
+/// log aggregators without regex-scraping prose.
+///
+/// Requires the `json_logging` feature.
+///
+/// Each line has a `"kind"` field (`"info"`, `"connection_accepted"`, `"connection_refused"`, or
+/// `"server_error"`) plus whatever fields are relevant to that `LogEvent` variant.
+///
+/// # Example
+///
+/// ```
+/// use server::{JsonLogger, Logger};
+///
+/// JsonLogger::new().info("listening on 0.0.0.0:1337");
+/// // prints: {"kind":"info","message":"listening on 0.0.0.0:1337"}
+/// ```
+#[cfg(feature = "json_logging")]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct JsonLogger;
+#[cfg(feature = "json_logging")]
+impl JsonLogger {
+    /// Make a new `JsonLogger`.
+    #[allow(dead_code)]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {}
+    }
+}
+#[cfg(feature = "json_logging")]
+impl Logger for JsonLogger {
+    fn log_event(&self, event: LogEvent) {
+        #[derive(Serialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum Line<'a> {
+            Info { message: &'a str },
+            ConnectionAccepted { peer: std::net::SocketAddr, path: &'a str },
+            ConnectionRefused { reason: &'a str },
+            ServerError { message: &'a str },
+        }
+
+        let line = match event {
+            LogEvent::Info(message) => Line::Info { message },
+            LogEvent::ConnectionAccepted { peer, path } => Line::ConnectionAccepted { peer, path },
+            LogEvent::ConnectionRefused { reason } => Line::ConnectionRefused { reason },
+            LogEvent::ServerError { message } => Line::ServerError { message },
+        };
+
+        match serde_json::to_string(&line) {
+            Ok(json) => println!("{json}"),
+            Err(e) => eprintln!("failed to serialize log event as json: {e}"),
+        }
+    }
 }
 
 pub use private::{Key, Nonce};
@@ -360,6 +468,252 @@ impl fmt::Display for NonceError {
     }
 }
 
+pub use shutdown::ShutdownHandle;
+
+/// A handle to gracefully stop a running `Server`.
+///
+/// This exists in its own module for the same reason `private` does: the atomic flag backing it
+/// shouldn't be constructible or inspectable from outside, only flippable via `shutdown`.
+mod shutdown {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// A cloneable handle to gracefully stop a running `Server`.
+    ///
+    /// Obtain one with `Server::shutdown_handle` before spawning the thread that calls
+    /// `Server::run`. Calling `shutdown` on any clone stops the server for all of them: it stops
+    /// accepting new connections, finishes any already in flight, and `run` returns `Ok(())`.
+    #[derive(Debug, Clone, Default)]
+    pub struct ShutdownHandle(Arc<AtomicBool>);
+
+    impl ShutdownHandle {
+        /// Creates a new handle, not yet triggered.
+        #[must_use]
+        pub(super) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Triggers the shutdown.
+        pub fn shutdown(&self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+
+        /// Returns `true` if `shutdown` has been called on this handle or a clone of it.
+        pub(super) fn is_shutdown(&self) -> bool {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+}
+
+/// A bidirectional network connection used by the server.
+///
+/// This exists so `Server::run` can treat a plaintext `TcpStream` and a (when the `tls` feature
+/// is enabled) TLS session identically--both get boxed as a `dyn Connection` and the rest of the
+/// server, including `validate_connection` and `Response::write_to`, never needs to know which
+/// one it has.
+trait Connection: Read + Write {
+    /// The address of the remote end of this connection.
+    fn peer_addr(&self) -> io::Result<std::net::SocketAddr>;
+    /// Shuts down both the send and receive halves of this connection.
+    fn shutdown(&self) -> io::Result<()>;
+    /// Sets the read and write timeout of this connection to `timeout`, or clears it (blocking
+    /// reads/writes indefinitely) if `timeout` is `None`.
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn peer_addr(&self) -> io::Result<std::net::SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Connection for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+    fn peer_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.sock.peer_addr()
+    }
+    fn shutdown(&self) -> io::Result<()> {
+        self.sock.shutdown(Shutdown::Both)
+    }
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)?;
+        self.sock.set_write_timeout(timeout)
+    }
+}
+
+/// TLS support for the server, kept behind the `tls` feature since most users run this on a
+/// trusted LAN and don't need the dependency.
+#[cfg(feature = "tls")]
+mod tls {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use rustls::ServerConfig;
+
+    use super::ConfigError;
+
+    /// The certificate and private key a `Server` uses to terminate TLS connections.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+    pub struct TlsConfig {
+        /// Path to a PEM-encoded certificate chain.
+        pub cert_path: PathBuf,
+        /// Path to the PEM-encoded private key matching `cert_path`.
+        pub key_path: PathBuf,
+    }
+
+    impl TlsConfig {
+        /// Loads the certificate chain and private key from disk and builds the `rustls` server
+        /// config used to accept TLS connections.
+        pub(super) fn build(&self) -> Result<Arc<ServerConfig>, ConfigError> {
+            let certs = rustls_pemfile::certs(&mut BufReader::new(
+                File::open(&self.cert_path)
+                    .map_err(|_| ConfigError::TlsCertNotFound(self.cert_path.clone()))?,
+            ))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ConfigError::InvalidTlsCertificate)?;
+
+            let key = rustls_pemfile::private_key(&mut BufReader::new(
+                File::open(&self.key_path)
+                    .map_err(|_| ConfigError::TlsKeyNotFound(self.key_path.clone()))?,
+            ))
+            .map_err(|_| ConfigError::InvalidTlsKey)?
+            .ok_or(ConfigError::InvalidTlsKey)?;
+
+            ServerConfig::builder()
+                .with_no_client_auth()
+                .with_single_cert(certs, key)
+                .map_err(|_| ConfigError::InvalidTlsCertificate)
+                .map(Arc::new)
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+pub use tls::TlsConfig;
+
+/// A `rustls::ServerConfig` that doesn't otherwise implement `Debug`.
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+struct TlsServerConfig(std::sync::Arc<rustls::ServerConfig>);
+#[cfg(feature = "tls")]
+impl fmt::Debug for TlsServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TlsServerConfig(..)")
+    }
+}
+
+/// Response compression.
+///
+/// `CompressionConfig` itself is always available so it round-trips through config files
+/// regardless of build configuration, but actually compressing anything requires the
+/// `compression` feature--without it, `negotiate` always returns `None` and responses go out
+/// uncompressed.
+mod compression {
+    /// Settings controlling how `Response::write_to` compresses response bodies.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+    pub struct CompressionConfig {
+        /// The compression level, from `0` (fastest, least compression) to `11` (slowest, most
+        /// compression). Levels are clamped to whatever the negotiated coding actually supports.
+        pub level: u32,
+        /// Whether to also compress `ResponseContent::Png` and `ResponseContent::Jpeg` content.
+        ///
+        /// PNG and JPEG are already compressed, so recompressing it burns CPU for little to no size
+        /// reduction--this defaults to `false`.
+        pub compress_images: bool,
+    }
+
+    /// The content codings this server can produce, in descending preference order.
+    #[cfg(feature = "compression")]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum Encoding {
+        Brotli,
+        Gzip,
+        Deflate,
+    }
+
+    #[cfg(feature = "compression")]
+    impl Encoding {
+        const PREFERENCE_ORDER: [Self; 3] = [Self::Brotli, Self::Gzip, Self::Deflate];
+
+        /// The `Content-Encoding` token for this coding.
+        fn token(self) -> &'static str {
+            match self {
+                Self::Brotli => "br",
+                Self::Gzip => "gzip",
+                Self::Deflate => "deflate",
+            }
+        }
+
+        /// Picks the first coding in `PREFERENCE_ORDER` that also appears in `accept_encoding` (a
+        /// client's parsed `Accept-Encoding` tokens).
+        fn negotiate(accept_encoding: &[String]) -> Option<Self> {
+            Self::PREFERENCE_ORDER
+                .into_iter()
+                .find(|encoding| accept_encoding.iter().any(|tok| tok == encoding.token()))
+        }
+    }
+
+    /// Negotiates a coding with `accept_encoding` and compresses `content` at `config.level`,
+    /// returning the compressed bytes and the `Content-Encoding` token to send.
+    ///
+    /// Returns `None` if the client's `Accept-Encoding` (no header at all, or only `identity`)
+    /// doesn't include a coding this server supports.
+    #[cfg(feature = "compression")]
+    pub(super) fn negotiate(
+        content: &[u8],
+        accept_encoding: &[String],
+        config: &CompressionConfig,
+    ) -> Option<(Vec<u8>, &'static str)> {
+        use std::io::Write;
+
+        let encoding = Encoding::negotiate(accept_encoding)?;
+        let mut out = Vec::new();
+        match encoding {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(&mut out, 4096, config.level.min(11), 22);
+                writer.write_all(content).expect("compressing into a Vec can't fail");
+            }
+            Encoding::Gzip => {
+                let mut writer = flate2::write::GzEncoder::new(
+                    &mut out,
+                    flate2::Compression::new(config.level.min(9)),
+                );
+                writer.write_all(content).expect("compressing into a Vec can't fail");
+                writer.finish().expect("compressing into a Vec can't fail");
+            }
+            Encoding::Deflate => {
+                let mut writer = flate2::write::DeflateEncoder::new(
+                    &mut out,
+                    flate2::Compression::new(config.level.min(9)),
+                );
+                writer.write_all(content).expect("compressing into a Vec can't fail");
+                writer.finish().expect("compressing into a Vec can't fail");
+            }
+        }
+        Some((out, encoding.token()))
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub(super) fn negotiate(
+        _content: &[u8],
+        _accept_encoding: &[String],
+        _config: &CompressionConfig,
+    ) -> Option<(Vec<u8>, &'static str)> {
+        None
+    }
+}
+pub use compression::CompressionConfig;
+
 /// The server.
 ///
 /// Call `Server::run` to run it.
@@ -368,17 +722,32 @@ impl fmt::Display for NonceError {
 /// authorization.
 #[derive(Debug)]
 pub struct Server<L: Logger> {
-    /// The socket address to listen on.
-    pub addr: SocketAddrV4,
+    /// The ip address to listen on.
+    pub addr: Ipv4Addr,
+    /// The range of ports to try binding to, in order, when `run` starts listening.
+    pub port_range: PortRange,
     /// The key to used to validate the connection.
     ///
     /// The key needs to be used by the client to generate the secret used to validate the request.
     pub key: Key,
+    /// The maximum number of connections handled at once.
+    ///
+    /// Connections accepted beyond this limit wait until a handler thread frees up.
+    pub max_connections: usize,
     /// The logger `Logger` used to log events including general information on connections and
     /// errors.
     ///
     /// If you'd like to ignore log information, use an instance of `DummyLogger`.
     pub logger: L,
+    /// The handle used to gracefully stop `run`. See `Server::shutdown_handle`.
+    shutdown: ShutdownHandle,
+    /// The response compression settings, if compression is enabled.
+    ///
+    /// Actually compressing anything also requires the `compression` feature.
+    pub compression: Option<CompressionConfig>,
+    /// The TLS configuration to terminate connections with, if TLS is enabled for this server.
+    #[cfg(feature = "tls")]
+    tls_config: Option<TlsServerConfig>,
 }
 
 // The config methods need a concrete type for logger, so we use the dummy logger as a dummy type.
@@ -422,7 +791,7 @@ impl<L: Logger> Server<L> {
     /// ```text
     /// key = string (32 printable ascii chars, can't begin or end with a space)
     /// address =
-    /// port = u16 (0 - 65535)
+    /// port = "u16" or "u16-u16" (a single port, or an inclusive range to try in order)
     /// ```
     #[allow(dead_code)]
     pub fn from_config_file(logger: L) -> Result<Self, ConfigError> {
@@ -431,72 +800,432 @@ impl<L: Logger> Server<L> {
             .and_then(|c| Config::build(c, logger))
     }
 
+    /// Returns a handle that can be used to gracefully stop a call to `run`.
+    ///
+    /// Get this before spawning the thread `run` is called on.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::thread;
+    /// use server::{DummyLogger, Key, Server};
+    ///
+    /// let server = Server::builder()
+    ///     .on_localhost()
+    ///     .with_port(0)
+    ///     .with_key(Key::new("this is a key and it's 32 bytes.").unwrap())
+    ///     .build(DummyLogger::new())
+    ///     .unwrap();
+    ///
+    /// let shutdown = server.shutdown_handle();
+    /// let running = thread::spawn(move || server.run(|_| unreachable!()));
+    ///
+    /// shutdown.shutdown();
+    /// assert!(running.join().unwrap().is_ok());
+    /// ```
+    #[must_use]
+    #[allow(dead_code)]
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
     /// Run the server.
     ///
-    /// This function will only exit if an error occurs.
-    pub fn run(&self, f: impl Fn(Request) -> Response) -> Result<(), std::io::Error> {
-        let mut buf = vec![0u8; 4096];
-        let mut nonce = Nonce::new(Duration::from_secs(2));
-        let listener = TcpListener::bind(self.addr)?;
+    /// Accepted connections are dispatched to a pool of `self.max_connections` worker threads, so
+    /// a slow client can't starve the others out. `f` is called from whichever worker thread picks
+    /// up the connection, so it needs to be `Sync`.
+    ///
+    /// Returns `Ok(())` once a shutdown is triggered with a handle from `shutdown_handle`, after
+    /// any in-flight connections finish. Otherwise, this function only exits if an error occurs.
+    pub fn run(&self, f: impl Fn(Request) -> Response + Sync) -> Result<(), std::io::Error>
+    where
+        L: Sync,
+    {
+        /// How often the accept loop wakes up to check whether a shutdown was requested.
+        const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+        let nonce = Mutex::new(Nonce::new(Duration::from_secs(2)));
+        let listener = self.bind()?;
+        listener.set_nonblocking(true)?;
+
+        self.logger.info(&format!(
+            "Listening on {}",
+            listener.local_addr().unwrap_or(SocketAddrV4::new(self.addr, 0).into())
+        ));
+
+        // A rendezvous channel (capacity 0): `tx.send` blocks until a worker is ready to receive,
+        // which is what bounds concurrency to `self.max_connections`.
+        let (tx, rx) = mpsc::sync_channel::<TcpStream>(0);
+        let rx = Mutex::new(rx);
+
+        thread::scope(|scope| {
+            for _ in 0..self.max_connections {
+                scope.spawn(|| loop {
+                    // Bind and drop the lock before handling the connection--`while let`'s
+                    // scrutinee temporary would otherwise hold the `Receiver` lock for the whole
+                    // loop body, letting only one worker run `handle_connection` at a time.
+                    let stream = match rx.lock().unwrap().recv() {
+                        Ok(stream) => stream,
+                        Err(_) => break,
+                    };
+                    self.handle_connection(stream, &f, &nonce);
+                });
+            }
+
+            while !self.shutdown.is_shutdown() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        // On some platforms (Windows among them) an accepted socket inherits the
+                        // listener's non-blocking mode; the workers rely on blocking reads with
+                        // timeouts, so switch it back.
+                        if let Err(e) = stream.set_nonblocking(false) {
+                            self.logger.connection_refused(&e.to_string());
+                            continue;
+                        }
+                        // Only fails if every worker has hung up, which can't happen--they only
+                        // stop looping when this end of the channel is dropped, below.
+                        let _ = tx.send(stream);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                    }
+                    Err(e) => self.logger.connection_refused(&e.to_string()),
+                }
+            }
+
+            // Closes the channel, which lets each worker finish its current connection (if any)
+            // and then exit once there's nothing left queued.
+            drop(tx);
+        });
 
-        self.logger.info(&format!("Listening on {}", self.addr));
+        Ok(())
+    }
 
-        for stream in listener.incoming() {
-            let Ok(mut stream) = stream.log_connection_refused(&self.logger) else {
-                continue;
+    /// Handles an accepted connection: the TLS handshake (if enabled), then validating and
+    /// running `f` on each `Request` read off it in turn, for as long as the client keeps the
+    /// connection alive.
+    ///
+    /// Requests keep coming on the same connection until the client sends `Connection: close`,
+    /// closes the connection, or goes quiet for longer than the read timeout set below--whichever
+    /// comes first. A `101` (WebSocket upgrade) response always ends the loop: `write_to` has
+    /// already spent the rest of the connection's life running the frame loop by the time it
+    /// returns.
+    ///
+    /// A panic from `f` is caught and logged as a server error rather than taking down the worker
+    /// thread (and, with it, one slot of the connection pool); it also ends the loop, since by
+    /// that point we don't know what state the connection is in.
+    fn handle_connection(
+        &self,
+        stream: TcpStream,
+        f: &(impl Fn(Request) -> Response + Sync),
+        nonce: &Mutex<Nonce>,
+    ) {
+        let mut stream: Box<dyn Connection> = match self.accept(stream) {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.logger.connection_refused(&e.to_string());
+                return;
+            }
+        };
+
+        if stream
+            .set_timeouts(Some(Duration::from_secs(2)))
+            .log_connection_refused(&self.logger)
+            .is_err()
+        {
+            let _ = stream.shutdown().log_connection_refused(&self.logger);
+            return;
+        };
+
+        let mut buf = Vec::new();
+        let mut first_request = true;
+
+        loop {
+            let request = match self.validate_connection(&mut *stream, &mut buf, nonce) {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(e) => {
+                    // On a reused connection, the common case is simply that the client has gone
+                    // idle or hung up--not worth logging as a refused connection every time.
+                    if first_request {
+                        self.logger.connection_refused(&e.to_string());
+                    }
+                    break;
+                }
+            };
+            first_request = false;
+
+            let keep_alive = request.keep_alive;
+            let accept_encoding = request.accept_encoding.clone();
+            let response = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(request)));
+            let mut response = match response {
+                Ok(response) => response,
+                Err(e) => {
+                    let msg = e
+                        .downcast_ref::<&str>()
+                        .copied()
+                        .or_else(|| e.downcast_ref::<String>().map(String::as_str))
+                        .unwrap_or("request handler panicked");
+                    self.logger.server_error(&format!("request handler panicked: {msg}"));
+                    break;
+                }
             };
-            if stream
-                .set_read_timeout(Some(Duration::from_secs(2)))
-                .and_then(|()| stream.set_write_timeout(Some(Duration::from_secs(2))))
-                .log_connection_refused(&self.logger)
+
+            let is_upgrade = response.status == 101;
+            if response
+                .write_to(&mut *stream, &accept_encoding, self.compression.as_ref(), keep_alive)
                 .is_err()
+                || !keep_alive
+                || is_upgrade
             {
-                let _ = stream
-                    .shutdown(Shutdown::Both)
-                    .log_connection_refused(&self.logger);
-                continue;
-            };
+                break;
+            }
+        }
+
+        // On the invalid-request path, `validate_connection` already shut the stream down--this
+        // is just a courtesy for every other path, so the resulting `NotConnected` isn't a fresh
+        // refusal worth logging.
+        if let Err(e) = stream.shutdown() {
+            if e.kind() != io::ErrorKind::NotConnected {
+                self.logger.connection_refused(&e.to_string());
+            }
+        }
+    }
+
+    /// Binds a `TcpListener`, trying each port in `self.port_range` in order and returning as
+    /// soon as one succeeds.
+    ///
+    /// If every port in the range is already in use, the error from the last attempt is returned.
+    fn bind(&self) -> io::Result<TcpListener> {
+        let mut last_err = None;
+
+        for port in self.port_range.iter() {
+            match TcpListener::bind(SocketAddrV4::new(self.addr, port)) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "port range is empty")
+        }))
+    }
 
-            self.validate_connection(&mut stream, &mut buf, &mut nonce)
-                .log_connection_refused(&self.logger)
-                .ok()
-                .flatten()
-                .map(|r| f(r).write_to(&mut stream));
-            let _ = stream
-                .shutdown(Shutdown::Both)
-                .log_connection_refused(&self.logger);
+    /// Wraps an accepted `TcpStream` into a `Connection`, performing the TLS handshake if this
+    /// server has TLS configured.
+    fn accept(&self, stream: TcpStream) -> io::Result<Box<dyn Connection>> {
+        #[cfg(feature = "tls")]
+        if let Some(tls_config) = &self.tls_config {
+            let conn = rustls::ServerConnection::new(tls_config.0.clone())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return Ok(Box::new(rustls::StreamOwned::new(conn, stream)));
         }
 
-        unreachable!();
+        Ok(Box::new(stream))
     }
 
-    /// Receives and validates an incoming connection, returning `Ok(Some(...))` if it's valid and
+    /// Reads and validates one request off `stream`, returning `Ok(Some(...))` if it's valid and
     /// `Ok(None)` if it isn't.
+    ///
+    /// `buf` carries any bytes read past the end of the previous request (from pipelining, or
+    /// just a read that happened to return more than one request's worth) over to the next call,
+    /// so keep-alive connections don't lose them. It's read from (growing as needed) until it
+    /// holds a full request--the headers, the blank line that ends them, and as many body bytes
+    /// as the `Content-Length` header declares--then exactly that much is drained back off the
+    /// front for `Request::new` to parse, leaving anything past it for next time.
     fn validate_connection(
         &self,
-        stream: &mut TcpStream,
-        buf: &mut [u8],
-        last_nonce: &mut Nonce,
+        stream: &mut dyn Connection,
+        buf: &mut Vec<u8>,
+        last_nonce: &Mutex<Nonce>,
     ) -> io::Result<Option<Request>> {
-        let length = stream.read(buf)?;
-        let buf = &buf[..length];
+        let mut chunk = [0u8; 4096];
 
-        Ok(match Request::new(buf, &self.key, last_nonce) {
-            Err(e) => {
-                self.logger.connection_refused(&e.to_string());
-                Response::from(&e).write_to(stream)?;
-                stream.shutdown(Shutdown::Both)?;
-                None
+        let header_end = loop {
+            if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i;
             }
-            Ok(request) => {
-                self.logger.info(&format!(
-                    "Got connection from {} to {}",
-                    stream.peer_addr()?,
-                    request.path
-                ));
-                Some(request)
+
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
             }
-        })
+            buf.extend_from_slice(&chunk[..n]);
+        };
+
+        let content_length: usize = std::str::from_utf8(&buf[..header_end])
+            .ok()
+            .and_then(|head| {
+                head.lines()
+                    .filter_map(|line| line.split_once(": "))
+                    .find(|(k, _)| *k == "Content-Length")
+                    .and_then(|(_, v)| v.parse().ok())
+            })
+            .unwrap_or(0);
+        let request_end = header_end + 4 + content_length;
+
+        while buf.len() < request_end {
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        let request_buf: Vec<u8> = buf.drain(..request_end).collect();
+
+        Ok(
+            match Request::new(&request_buf, &self.key, &mut last_nonce.lock().unwrap()) {
+                Err(e) => {
+                    self.logger.connection_refused(&e.to_string());
+                    Response::from(&e).write_to(stream, &[], None, false)?;
+                    stream.shutdown()?;
+                    None
+                }
+                Ok(request) => {
+                    self.logger.log_event(LogEvent::ConnectionAccepted {
+                        peer: stream.peer_addr()?,
+                        path: &request.path,
+                    });
+                    Some(request)
+                }
+            },
+        )
+    }
+}
+
+/// A range of ports to try binding to, in order.
+///
+/// Parses from (and displays as) `"start-end"`, or just `"port"` when the range is a single port.
+///
+/// # Example
+///
+/// ```
+/// use server::PortRange;
+///
+/// assert_eq!("1337".parse::<PortRange>(), Ok(PortRange::single(1337)));
+/// assert_eq!("1337-1340".parse::<PortRange>(), Ok(PortRange::new(1337, 1340)));
+/// assert_eq!(PortRange::single(1337).to_string(), "1337");
+/// assert_eq!(PortRange::new(1337, 1340).to_string(), "1337-1340");
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PortRange {
+    /// The first port in the range (inclusive).
+    start: u16,
+    /// The last port in the range (inclusive).
+    end: u16,
+}
+
+impl PortRange {
+    /// Creates a new `PortRange` spanning a single port.
+    #[must_use]
+    pub const fn single(port: u16) -> Self {
+        Self {
+            start: port,
+            end: port,
+        }
+    }
+
+    /// Creates a new `PortRange` spanning `start..=end`.
+    ///
+    /// If `end` is less than `start`, they're swapped so the range is always non-empty.
+    #[must_use]
+    pub const fn new(start: u16, end: u16) -> Self {
+        if end < start {
+            Self {
+                start: end,
+                end: start,
+            }
+        } else {
+            Self { start, end }
+        }
+    }
+
+    /// The first port in the range.
+    #[must_use]
+    fn first(&self) -> u16 {
+        self.start
+    }
+
+    /// Iterates over every port in the range, in order.
+    fn iter(&self) -> impl Iterator<Item = u16> {
+        self.start..=self.end
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = ParsePortRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((start, end)) => Ok(Self::new(
+                start.trim().parse()?,
+                end.trim().parse()?,
+            )),
+            None => Ok(Self::single(s.trim().parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl Serialize for PortRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PortRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = PortRange;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a port (\"1337\") or port range (\"1337-1340\")")
+            }
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                v.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor {})
+    }
+}
+
+/// Occurs when parsing a `PortRange` from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePortRangeError(std::num::ParseIntError);
+
+impl Error for ParsePortRangeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+impl fmt::Display for ParsePortRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid port range: {}", self.0)
+    }
+}
+impl From<std::num::ParseIntError> for ParsePortRangeError {
+    fn from(err: std::num::ParseIntError) -> Self {
+        Self(err)
     }
 }
 
@@ -515,6 +1244,7 @@ impl<L: Logger> Server<L> {
 ///
 /// ### Default attributes & values
 ///  - `address` defaults to `0.0.0.0` (lan)
+///  - `max_connections` defaults to `8`
 ///
 /// # Example
 ///
@@ -536,16 +1266,58 @@ pub struct Config {
     /// Defaults to `0.0.0.0` (lan).
     #[serde(rename = "address")]
     pub addr: Option<Ipv4Addr>,
-    /// The port to host on.
+    /// The port (or range of ports, tried in order) to host on.
     ///
     /// Calling the `build` method will fail if this isn't set.
-    pub port: Option<u16>,
+    pub port: Option<PortRange>,
     /// The key used to validate the connection.
     ///
     /// Calling the `build` method will fail if this isn't set. See `Key`'s docs for what
     /// constitutes a valid key.
     pub key: Option<Key>,
+    /// The maximum number of connections handled at once.
+    ///
+    /// Defaults to `8` if unset.
+    pub max_connections: Option<usize>,
+    /// The response compression settings.
+    ///
+    /// If unset, responses are never compressed. Actually compressing anything also requires the
+    /// `compression` feature--without it, this is recorded but has no effect.
+    pub compression: Option<CompressionConfig>,
+    /// The certificate/key pair to terminate TLS connections with, if TLS is enabled.
+    ///
+    /// Only present when the `tls` feature is enabled. If unset, the server accepts plaintext
+    /// connections.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+/// The template written by `Config::generate_default` in TOML format.
+const DEFAULT_CONFIG_TOML: &str = r#"# The ip address to host on. 0.0.0.0 listens on your local network; 127.0.0.1 restricts access to
+# this machine only.
+address = "0.0.0.0"
+
+# The key used to authenticate requests. Must be exactly 32 printable ascii characters and can't
+# start or end with a space. Replace this placeholder with your own key.
+key = "replace-with-a-32-character-key."
+
+# The port (or port range, e.g. "1337-1340") to listen on. Required.
+# port = "1337"
+"#;
+
+/// The template written by `Config::generate_default` in JSON format.
+///
+/// JSON has no comment syntax, so documentation is stashed in `"// field"` keys instead--`Config`
+/// ignores fields it doesn't recognize, so these are harmless if left in place.
+#[cfg(feature = "json_config")]
+const DEFAULT_CONFIG_JSON: &str = r#"{
+  "// address": "0.0.0.0 listens on your local network; 127.0.0.1 restricts access to this machine only",
+  "address": "0.0.0.0",
+  "// key": "must be exactly 32 printable ascii characters and can't start or end with a space; replace this placeholder with your own key",
+  "key": "replace-with-a-32-character-key.",
+  "// port": "the port (or port range, e.g. \"1337-1340\") to listen on; required"
 }
+"#;
 
 impl Config {
     /// Creates a new `Config`.
@@ -570,6 +1342,7 @@ impl Config {
     ///
     /// ### Default attributes & values
     ///  - `address` defaults to `0.0.0.0` (lan)
+    ///  - `max_connections` defaults to `8`
     ///
     /// # Example
     ///
@@ -577,11 +1350,11 @@ impl Config {
     /// // config.toml:
     /// //
     /// // address = "127.0.0.1"
-    /// // port = 1337
+    /// // port = "1337"
     /// // key = "this is a key and it's 32 bytes."
     ///
-    /// use std::net::{Ipv4Addr, SocketAddrV4};
-    /// use server::{Key, DummyLogger, Server};
+    /// use std::net::Ipv4Addr;
+    /// use server::{Key, DummyLogger, PortRange, Server};
     ///
     /// let server = Server::builder()
     ///     .from_config_file()
@@ -589,7 +1362,8 @@ impl Config {
     ///     .build(DummyLogger::new())
     ///     .expect("file didn't contain all necessary items");
     ///
-    /// assert_eq!(server.addr, SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 1337));
+    /// assert_eq!(server.addr, Ipv4Addr::new(127, 0, 0, 1));
+    /// assert_eq!(server.port_range, PortRange::single(1337));
     /// assert_eq!(server.key, Key::new("this is a key and it's 32 bytes.").unwrap());
     /// ```
     #[allow(dead_code, clippy::wrong_self_convention)]
@@ -599,9 +1373,11 @@ impl Config {
         self.from_specific_file(dir)
     }
 
-    /// Update this `Config` from a `.toml` file.
+    /// Update this `Config` from a `.toml` file, or from a `.json` file (requires the
+    /// `json_config` feature)--the format is picked based on `config_file`'s extension, falling
+    /// back to TOML for anything else.
     ///
-    /// Note that the `addr` attribute is called `address` in the toml file.
+    /// Note that the `addr` attribute is called `address` in the config file.
     ///
     /// If an attribute is set on this `Config` and isn't in the specified file, it's value will be
     /// maintained. In other words, you can intermix reading config attributes from the file and
@@ -614,6 +1390,7 @@ impl Config {
     ///
     /// ### Default attributes & values
     ///  - `address` defaults to `0.0.0.0` (lan)
+    ///  - `max_connections` defaults to `8`
     ///
     /// # Example
     ///
@@ -621,11 +1398,11 @@ impl Config {
     /// // a/b/c/my_config_file.toml:
     /// //
     /// // address = "127.0.0.1"
-    /// // port = 1337
+    /// // port = "1337"
     /// // key = "this is a key and it's 32 bytes."
     ///
-    /// use std::net::{Ipv4Addr, SocketAddrV4};
-    /// use server::{DummyLogger, Key, Server};
+    /// use std::net::Ipv4Addr;
+    /// use server::{DummyLogger, Key, PortRange, Server};
     ///
     /// let server = Server::builder()
     ///     .from_specific_file("a/b/c/my_config_file.toml")
@@ -633,7 +1410,8 @@ impl Config {
     ///     .build(DummyLogger::new())
     ///     .expect("file didn't contain all necessary items");
     ///
-    /// assert_eq!(server.addr, SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 1337));
+    /// assert_eq!(server.addr, Ipv4Addr::new(127, 0, 0, 1));
+    /// assert_eq!(server.port_range, PortRange::single(1337));
     /// assert_eq!(server.key, Key::new("this is a key and it's 32 bytes.").unwrap());
     /// ```
     #[allow(clippy::wrong_self_convention)]
@@ -650,15 +1428,54 @@ impl Config {
             }
             Err(e) => return Err(ConfigError::Io(e)),
         };
-        let new: Self = toml::from_str(&file_content)?;
+
+        let new: Self = match config_file.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "json_config")]
+            Some("json") => serde_json::from_str(&file_content)?,
+            _ => toml::from_str(&file_content)?,
+        };
 
         self.addr = new.addr.or(self.addr);
         self.port = new.port.or(self.port);
         self.key = new.key.or(self.key);
+        self.max_connections = new.max_connections.or(self.max_connections);
+        self.compression = new.compression.or(self.compression);
+        #[cfg(feature = "tls")]
+        {
+            self.tls = new.tls.or(self.tls);
+        }
 
         Ok(self)
     }
 
+    /// Writes a commented default config template to `path`, in TOML format, or JSON if `path`'s
+    /// extension is `.json` (requires the `json_config` feature).
+    ///
+    /// The template has a placeholder key, since a valid key can't be generated without user
+    /// input--see `Key`'s docs for what makes a key valid. `port` is left empty since there's no
+    /// sensible default port for this server. This is meant to give new users something to edit
+    /// rather than a file they can use as-is.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use server::Config;
+    ///
+    /// Config::generate_default("config.toml").expect("failed to write default config");
+    /// ```
+    #[allow(dead_code)]
+    pub fn generate_default(path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+
+        let content = match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "json_config")]
+            Some("json") => DEFAULT_CONFIG_JSON.to_owned(),
+            _ => DEFAULT_CONFIG_TOML.to_owned(),
+        };
+
+        std::fs::write(path, content)
+    }
+
     /// Gets the socket address of this `Config`.
     ///
     /// # Example
@@ -672,12 +1489,14 @@ impl Config {
     ///
     /// assert_eq!(config.sock_addr(), Some(SocketAddrV4::new(ip, 1337)));
     /// ```
+    ///
+    /// If the port is a range, the first port in the range is used.
     #[must_use]
     #[allow(dead_code)]
     pub fn sock_addr(&self) -> Option<SocketAddrV4> {
         self.addr
             .zip(self.port)
-            .map(|(addr, port)| SocketAddrV4::new(addr, port))
+            .map(|(addr, port)| SocketAddrV4::new(addr, port.first()))
     }
 
     /// Build this `Config` into a `Server`.
@@ -691,6 +1510,7 @@ impl Config {
     ///
     /// ### Default attributes & values
     ///  - `address` defaults to `0.0.0.0`
+    ///  - `max_connections` defaults to `8`
     ///
     /// # Example
     ///
@@ -707,12 +1527,25 @@ impl Config {
     pub fn build<L: Logger>(self, logger: L) -> Result<Server<L>, ConfigError> {
         let key = self.key.ok_or(ConfigError::MissingRequired("key"))?;
         let addr = self.addr.unwrap_or_else(|| Ipv4Addr::new(0, 0, 0, 0));
-        let port = self.port.ok_or(ConfigError::MissingRequired("port"))?;
+        let port_range = self.port.ok_or(ConfigError::MissingRequired("port"))?;
+        let max_connections = self.max_connections.unwrap_or(8);
+
+        #[cfg(feature = "tls")]
+        let tls_config = self
+            .tls
+            .map(|tls| tls.build().map(TlsServerConfig))
+            .transpose()?;
 
         Ok(Server {
-            addr: SocketAddrV4::new(addr, port),
+            addr,
+            port_range,
             key,
+            max_connections,
             logger,
+            shutdown: ShutdownHandle::new(),
+            compression: self.compression,
+            #[cfg(feature = "tls")]
+            tls_config,
         })
     }
 
@@ -774,15 +1607,33 @@ impl Config {
     /// # Example
     ///
     /// ```
-    /// use server::Server;
+    /// use server::{PortRange, Server};
     ///
     /// let config = Server::builder().with_port(1337);
     ///
-    /// assert_eq!(config.port, Some(1337));
+    /// assert_eq!(config.port, Some(PortRange::single(1337)));
     /// ```
     #[allow(dead_code)]
     pub fn with_port(mut self, port: u16) -> Self {
-        self.port = Some(port);
+        self.port = Some(PortRange::single(port));
+        self
+    }
+    /// Sets the port range of this `Config`.
+    ///
+    /// If no port in the range is free, `Server::run` will fail to bind.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use server::{PortRange, Server};
+    ///
+    /// let config = Server::builder().with_port_range(1337, 1340);
+    ///
+    /// assert_eq!(config.port, Some(PortRange::new(1337, 1340)));
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_port_range(mut self, start: u16, end: u16) -> Self {
+        self.port = Some(PortRange::new(start, end));
         self
     }
     /// Sets the socket address of this `Config`.
@@ -801,7 +1652,7 @@ impl Config {
     #[allow(dead_code)]
     pub fn with_sock_addr(mut self, sock_addr: SocketAddrV4) -> Self {
         self.addr = Some(*sock_addr.ip());
-        self.port = Some(sock_addr.port());
+        self.port = Some(PortRange::single(sock_addr.port()));
         self
     }
     /// Sets the key of this `Config`.
@@ -821,6 +1672,75 @@ impl Config {
         self.key = Some(key);
         self
     }
+    /// Sets the maximum number of connections this `Config`'s server handles at once.
+    ///
+    /// Connections accepted beyond this limit wait until a handler thread frees up. Defaults to
+    /// `8` if unset.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use server::Server;
+    ///
+    /// let config = Server::builder().with_max_connections(32);
+    ///
+    /// assert_eq!(config.max_connections, Some(32));
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+    /// Enables response compression at `level` (`0`, fastest/least compression, to `11`,
+    /// slowest/most compression).
+    ///
+    /// Only `ResponseContent::Text` (and `ResponseContent::Json`) is compressed by
+    /// default--`ResponseContent::Png` and `ResponseContent::Jpeg` are already compressed, so
+    /// recompressing them is usually wasted CPU. Flip `compress_images` on the
+    /// returned config's `compression` field if you want it compressed anyway.
+    ///
+    /// Requires the `compression` feature to actually compress anything; without it, this is
+    /// recorded but has no effect, which is an easy way to disable compression on CPU-constrained
+    /// hosts without touching call sites.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use server::Server;
+    ///
+    /// let config = Server::builder().with_compression(6);
+    ///
+    /// assert_eq!(config.compression.unwrap().level, 6);
+    /// ```
+    #[allow(dead_code)]
+    pub fn with_compression(mut self, level: u32) -> Self {
+        self.compression = Some(CompressionConfig {
+            level,
+            compress_images: false,
+        });
+        self
+    }
+    /// Enables TLS on this `Config`, terminating connections with the certificate chain and
+    /// private key at the given paths (both PEM-encoded).
+    ///
+    /// The certificate and key aren't read or validated until `build` is called.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use server::Server;
+    ///
+    /// let config = Server::builder().with_tls("cert.pem", "key.pem");
+    /// ```
+    #[cfg(feature = "tls")]
+    #[allow(dead_code)]
+    pub fn with_tls(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.tls = Some(TlsConfig {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        });
+        self
+    }
 }
 
 /// Returned when there is an error with parsing or building a `Config`.
@@ -844,6 +1764,24 @@ pub enum ConfigError {
     /// Invalid addresses, ports, keys, etc... in a config file are considered toml errors and
     /// wrapped in this type.
     Toml(toml::de::Error),
+    /// Returned when a json error is encountered when parsing a `.json` config file.
+    ///
+    /// Invalid addresses, ports, keys, etc... in a config file are considered json errors and
+    /// wrapped in this type.
+    #[cfg(feature = "json_config")]
+    Json(serde_json::Error),
+    /// Returned when the TLS certificate file can't be found.
+    #[cfg(feature = "tls")]
+    TlsCertNotFound(PathBuf),
+    /// Returned when the TLS private key file can't be found.
+    #[cfg(feature = "tls")]
+    TlsKeyNotFound(PathBuf),
+    /// Returned when the TLS certificate file doesn't contain a valid PEM certificate chain.
+    #[cfg(feature = "tls")]
+    InvalidTlsCertificate,
+    /// Returned when the TLS key file doesn't contain a valid PEM private key.
+    #[cfg(feature = "tls")]
+    InvalidTlsKey,
 }
 
 impl Error for ConfigError {
@@ -853,6 +1791,13 @@ impl Error for ConfigError {
             Self::Io(e) => Some(e),
             Self::FileNotFound(_) => None,
             Self::Toml(e) => Some(e),
+            #[cfg(feature = "json_config")]
+            Self::Json(e) => Some(e),
+            #[cfg(feature = "tls")]
+            Self::TlsCertNotFound(_)
+            | Self::TlsKeyNotFound(_)
+            | Self::InvalidTlsCertificate
+            | Self::InvalidTlsKey => None,
         }
     }
 }
@@ -861,6 +1806,18 @@ impl fmt::Display for ConfigError {
         match self {
             Self::MissingRequired(field) => write!(f, "no {field} set"),
             Self::FileNotFound(path) => write!(f, "no config file found at {}", path.display()),
+            #[cfg(feature = "tls")]
+            Self::TlsCertNotFound(path) => {
+                write!(f, "no TLS certificate file found at {}", path.display())
+            }
+            #[cfg(feature = "tls")]
+            Self::TlsKeyNotFound(path) => {
+                write!(f, "no TLS private key file found at {}", path.display())
+            }
+            #[cfg(feature = "tls")]
+            Self::InvalidTlsCertificate => f.write_str("TLS certificate file is invalid"),
+            #[cfg(feature = "tls")]
+            Self::InvalidTlsKey => f.write_str("TLS private key file is invalid"),
             _ => fmt::Display::fmt(&self.source().unwrap(), f),
         }
     }
@@ -878,6 +1835,12 @@ impl From<io::Error> for ConfigError {
         Self::Io(err)
     }
 }
+#[cfg(feature = "json_config")]
+impl From<serde_json::Error> for ConfigError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
 impl From<toml::de::Error> for ConfigError {
     fn from(err: toml::de::Error) -> Self {
         Self::Toml(err)
@@ -930,19 +1893,45 @@ pub struct Request {
     pub method: Method,
     /// The path the request was made to, including the slash.
     pub path: String,
+    /// The content codings from the client's `Accept-Encoding` header, lowercased and with any
+    /// `;q=...` weight stripped off.
+    ///
+    /// Empty if the header was absent. Used by `Response::write_to` to decide whether (and how)
+    /// to compress the response.
+    pub accept_encoding: Vec<String>,
+    /// The client's `Sec-WebSocket-Key` header, present when this request is a WebSocket upgrade
+    /// handshake (an `Upgrade: websocket` request).
+    ///
+    /// Pass this to `Response::websocket` to complete the handshake and start handling frames.
+    pub websocket_key: Option<String>,
+    /// The request body, as declared by a `Content-Length` header (empty if the header was
+    /// absent or zero).
+    pub body: Vec<u8>,
+    /// Whether the client asked for this connection to stay open for further requests (it didn't
+    /// send `Connection: close`).
+    ///
+    /// `Server::run` keeps reading further requests off the same connection for as long as this
+    /// stays `true` (and the response isn't a WebSocket upgrade).
+    pub keep_alive: bool,
 }
 
 impl Request {
-    /// Creates a new `Request`.
+    /// Creates a new `Request` from a complete request: `buf` must hold the headers, the blank
+    /// line that ends them, and exactly as many body bytes as the `Content-Length` header (if
+    /// any) declares--`Server::validate_connection` is responsible for reading that much off the
+    /// connection before calling this.
     ///
     /// The key and last nonce are required to validate the request. As all requests must be
     /// validated, any `Request` instance that exists is inherently a valid request.
     fn new(buf: &[u8], key: &Key, last_nonce: &mut Nonce) -> Result<Self, RequestError> {
         use RequestError::*;
 
-        // we take until the end of the headers (a blank line)
-        let buf_as_str = String::from_utf8_lossy(buf);
-        let mut lines = buf_as_str.lines().take_while(|line| !line.is_empty());
+        let header_end = buf
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or(MalformedHttp)?;
+        let head = String::from_utf8_lossy(&buf[..header_end]);
+        let mut lines = head.lines();
 
         let mut line1 = lines.next().ok_or(MalformedHttp)?.splitn(3, ' ');
         let method = line1.next().ok_or(MalformedHttp)?.try_into()?;
@@ -966,6 +1955,53 @@ impl Request {
             .1
             .parse()
             .map_err(|_| RequestError::MalformedHeaders)?;
+        let version: u32 = lines
+            .iter()
+            .find(|(k, _)| *k == "Version")
+            .ok_or(MissingVersion)?
+            .1
+            .parse()
+            .map_err(|_| RequestError::MalformedHeaders)?;
+
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+            return Err(UnsupportedVersion(version));
+        }
+
+        let accept_encoding = lines
+            .iter()
+            .find(|(k, _)| *k == "Accept-Encoding")
+            .map(|(_, v)| {
+                v.split(',')
+                    .filter(|tok| {
+                        // A `q=0` param is the client explicitly refusing this coding, not just
+                        // deprioritizing it--treat it the same as not listing the coding at all.
+                        tok.split(';')
+                            .skip(1)
+                            .find_map(|p| p.trim().strip_prefix("q="))
+                            .and_then(|q| q.trim().parse::<f32>().ok())
+                            .is_none_or(|q| q != 0.0)
+                    })
+                    .map(|tok| tok.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+                    .filter(|tok| !tok.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let is_upgrade = lines
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case("Upgrade") && v.eq_ignore_ascii_case("websocket"));
+        let websocket_key = is_upgrade
+            .then(|| {
+                lines
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("Sec-WebSocket-Key"))
+                    .map(|(_, v)| (*v).to_owned())
+            })
+            .flatten();
+
+        let keep_alive = !lines
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case("Connection") && v.eq_ignore_ascii_case("close"));
 
         let nonce_witness = last_nonce.begin_update(nonce)?;
         if key.generate_secret(nonce) == secret {
@@ -973,6 +2009,10 @@ impl Request {
             Ok(Self {
                 method,
                 path: path.to_owned(),
+                accept_encoding,
+                websocket_key,
+                body: buf[header_end + 4..].to_owned(),
+                keep_alive,
             })
         } else {
             Err(RequestError::InvalidKey)
@@ -991,6 +2031,13 @@ pub enum RequestError {
     MissingNonce,
     /// Occurs when the secret header is missing.
     MissingSecret,
+    /// Occurs when the version header is missing.
+    MissingVersion,
+    /// Occurs when the client's protocol version isn't one this server supports.
+    ///
+    /// This server supports `SUPPORTED_PROTOCOL_VERSIONS`; the contained value is the version the
+    /// client sent.
+    UnsupportedVersion(u32),
     /// Occurs when a request is made to an illegal endpoint.
     IllegalEndpoint(Cow<'static, str>),
     /// Occurs when the key is invalid (because the secret doesn't match).
@@ -1010,6 +2057,12 @@ impl fmt::Display for RequestError {
             MalformedHeaders => Borrowed("a header is malformed"),
             MissingNonce => Borrowed("nonce header not found"),
             MissingSecret => Borrowed("secret header not found"),
+            MissingVersion => Borrowed("version header not found"),
+            UnsupportedVersion(version) => Owned(format!(
+                "client protocol version {version} isn't supported by this server (supports {}-{})",
+                SUPPORTED_PROTOCOL_VERSIONS.start(),
+                SUPPORTED_PROTOCOL_VERSIONS.end(),
+            )),
             IllegalEndpoint(i) => Owned(format!("tried to reach illegal endpoint {i}")),
             InvalidKey => Borrowed("key is invalid"),
             NonceError(e) => Owned(e.to_string()),
@@ -1031,6 +2084,7 @@ impl From<&RequestError> for Response {
     fn from(value: &RequestError) -> Self {
         let status = match value {
             RequestError::InvalidKey => 401,
+            RequestError::UnsupportedVersion(_) => 426,
             _ => 400,
         };
         Self::from_message(status, value.to_string())
@@ -1046,6 +2100,13 @@ pub enum ResponseContent {
     Text(String),
     /// For when a response's content is a png.
     Png(Vec<u8>),
+    /// For when a response's content is a jpeg.
+    Jpeg(Vec<u8>),
+    /// For when a response's content is JSON, already serialized.
+    ///
+    /// Requires the `json_response` feature. See `Response::from_json`.
+    #[cfg(feature = "json_response")]
+    Json(Vec<u8>),
 }
 
 impl ResponseContent {
@@ -1060,6 +2121,9 @@ impl ResponseContent {
             None => &[],
             Text(s) => s.as_bytes(),
             Png(b) => b,
+            Jpeg(b) => b,
+            #[cfg(feature = "json_response")]
+            Json(b) => b,
         }
     }
 
@@ -1074,6 +2138,9 @@ impl ResponseContent {
             None => "",
             Text(_) => "Content-Type: text/plain; charset=utf-8\r\n",
             Png(_) => "Content-Type: image/png\r\n",
+            Jpeg(_) => "Content-Type: image/jpeg\r\n",
+            #[cfg(feature = "json_response")]
+            Json(_) => "Content-Type: application/json\r\n",
         }
     }
 
@@ -1088,17 +2155,277 @@ impl ResponseContent {
             None => 0,
             Text(s) => s.len(),
             Png(b) => b.len(),
+            Jpeg(b) => b.len(),
+            #[cfg(feature = "json_response")]
+            Json(b) => b.len(),
+        }
+    }
+
+    /// Whether this content should be compressed under `config`.
+    ///
+    /// `Text` and `Json` are always eligible; `Png` and `Jpeg` are already compressed, so they're
+    /// only eligible when `config.compress_images` opts in; `None` has nothing worth compressing.
+    #[must_use]
+    fn is_compression_eligible(&self, config: &CompressionConfig) -> bool {
+        use ResponseContent::*;
+
+        match self {
+            None => false,
+            Text(_) => true,
+            Png(_) | Jpeg(_) => config.compress_images,
+            #[cfg(feature = "json_response")]
+            Json(_) => true,
+        }
+    }
+
+    /// This content's `BodyKind`, for header generation.
+    ///
+    /// `None` is reported as `BodyKind::Empty`; everything else as `BodyKind::Sized`. Either way
+    /// a `Content-Length` header is emitted--`Empty` doesn't mean the header is omitted, just that
+    /// there's conceptually nothing there.
+    #[must_use]
+    fn body_kind(&self) -> BodyKind {
+        match self {
+            ResponseContent::None => BodyKind::Empty,
+            content => BodyKind::Sized(content.len()),
         }
     }
 }
 
+/// The WebSocket upgrade handshake and RFC 6455 data framing behind `Response::websocket`.
+mod websocket {
+    use std::fmt;
+    use std::io::{self, Read, Write};
+
+    use base64::Engine;
+    use sha1::{Digest, Sha1};
+
+    /// The GUID RFC 6455 section 1.3 mandates appending to the client's key before hashing.
+    const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+    /// Computes the `Sec-WebSocket-Accept` handshake header value from the client's
+    /// `Sec-WebSocket-Key`: `base64(sha1(key + GUID))`.
+    #[must_use]
+    pub(super) fn accept_key(client_key: &str) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(client_key.as_bytes());
+        hasher.update(GUID.as_bytes());
+        base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+    }
+
+    /// A text or binary WebSocket message, after frame parsing and unmasking.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum WsMessage {
+        /// A UTF-8 text message.
+        Text(String),
+        /// An arbitrary binary message.
+        Binary(Vec<u8>),
+    }
+
+    /// A handler for messages received over an upgraded WebSocket connection.
+    ///
+    /// See `Response::websocket`.
+    pub trait WebSocketHandler: fmt::Debug {
+        /// Handles an inbound text or binary message, optionally replying with one of its own.
+        ///
+        /// The reply (if any) is sent back unmasked, as the server is never supposed to mask its
+        /// frames. Returning `Err` closes the connection.
+        fn on_message(&mut self, msg: WsMessage) -> io::Result<Option<WsMessage>>;
+    }
+
+    /// An RFC 6455 frame opcode.
+    ///
+    /// Continuation frames (fragmented messages) aren't supported--every frame is treated as a
+    /// complete message.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    enum Opcode {
+        Text,
+        Binary,
+        Close,
+        Ping,
+        Pong,
+    }
+
+    impl Opcode {
+        fn from_byte(b: u8) -> Option<Self> {
+            match b {
+                0x1 => Some(Self::Text),
+                0x2 => Some(Self::Binary),
+                0x8 => Some(Self::Close),
+                0x9 => Some(Self::Ping),
+                0xA => Some(Self::Pong),
+                _ => None,
+            }
+        }
+
+        fn into_byte(self) -> u8 {
+            match self {
+                Self::Text => 0x1,
+                Self::Binary => 0x2,
+                Self::Close => 0x8,
+                Self::Ping => 0x9,
+                Self::Pong => 0xA,
+            }
+        }
+    }
+
+    /// A single parsed (and unmasked, if it was masked) frame.
+    struct Frame {
+        opcode: Opcode,
+        payload: Vec<u8>,
+    }
+
+    /// The largest payload `read_frame` will allocate for, in bytes. A client can claim any
+    /// 64-bit length in the frame header, so this caps it well short of a DoS-by-allocation while
+    /// staying generous for any message this server's handlers actually send/receive.
+    const MAX_FRAME_LEN: u64 = 16 * 1024 * 1024;
+
+    /// Reads and unmasks a single frame: the 2-byte header, the 7/16/64-bit length, the 4-byte
+    /// masking key (frames from a client must be masked), and the payload.
+    fn read_frame<S: Read + ?Sized>(stream: &mut S) -> io::Result<Frame> {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header)?;
+
+        let opcode = Opcode::from_byte(header[0] & 0x0F)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unsupported ws opcode"))?;
+        let masked = header[1] & 0x80 != 0;
+        let mut len = u64::from(header[1] & 0x7F);
+
+        if len == 126 {
+            let mut ext = [0u8; 2];
+            stream.read_exact(&mut ext)?;
+            len = u64::from(u16::from_be_bytes(ext));
+        } else if len == 127 {
+            let mut ext = [0u8; 8];
+            stream.read_exact(&mut ext)?;
+            len = u64::from_be_bytes(ext);
+        }
+
+        let mask = masked
+            .then(|| -> io::Result<_> {
+                let mut mask = [0u8; 4];
+                stream.read_exact(&mut mask)?;
+                Ok(mask)
+            })
+            .transpose()?;
+
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ws frame payload too large"));
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload)?;
+        if let Some(mask) = mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        Ok(Frame { opcode, payload })
+    }
+
+    /// Writes an unmasked frame--frames from the server to a client are never masked.
+    fn write_frame<S: Write + ?Sized>(stream: &mut S, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+        let mut header = vec![0x80 | opcode.into_byte()];
+        let len = payload.len();
+
+        if len <= 125 {
+            header.push(len as u8);
+        } else if len <= usize::from(u16::MAX) {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        stream.write_all(&header)?;
+        stream.write_all(payload)
+    }
+
+    /// Runs the frame loop for an upgraded connection until the client closes it, a read or write
+    /// fails, or `handler` returns `Err`.
+    ///
+    /// Pings are answered with a pong directly, without involving `handler`; text and binary
+    /// frames are handed to it, and its reply (if any) is sent back as its own frame.
+    pub(super) fn run<S: Read + Write + ?Sized>(
+        stream: &mut S,
+        handler: &mut dyn WebSocketHandler,
+    ) -> io::Result<()> {
+        loop {
+            let frame = read_frame(stream)?;
+
+            let msg = match frame.opcode {
+                Opcode::Close => {
+                    write_frame(stream, Opcode::Close, &frame.payload)?;
+                    return Ok(());
+                }
+                Opcode::Ping => {
+                    write_frame(stream, Opcode::Pong, &frame.payload)?;
+                    continue;
+                }
+                Opcode::Pong => continue,
+                Opcode::Text => WsMessage::Text(String::from_utf8(frame.payload).map_err(
+                    |_| io::Error::new(io::ErrorKind::InvalidData, "invalid utf-8 in text frame"),
+                )?),
+                Opcode::Binary => WsMessage::Binary(frame.payload),
+            };
+
+            if let Some(reply) = handler.on_message(msg)? {
+                match reply {
+                    WsMessage::Text(s) => write_frame(stream, Opcode::Text, s.as_bytes())?,
+                    WsMessage::Binary(b) => write_frame(stream, Opcode::Binary, &b)?,
+                }
+            }
+        }
+    }
+}
+
+pub use websocket::{WebSocketHandler, WsMessage};
+
+/// A source of response body bytes read incrementally, for bodies too large (or of indeterminate
+/// length, like a live screen capture) to buffer into a `ResponseContent` up front.
+///
+/// Used with `Response::from_stream`.
+pub trait ResponseBody: fmt::Debug {
+    /// Returns the next chunk of the body, or `Ok(None)` once the body is exhausted.
+    fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// The shape of a response body, for deciding which length-related header (if any) to emit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BodyKind {
+    /// No content--emits a `Content-Length: 0`.
+    Empty,
+    /// Content of a known length in bytes--emits `Content-Length`.
+    Sized(usize),
+    /// Content of an unknown length, read incrementally--emits `Transfer-Encoding: chunked`.
+    Streamed,
+}
+
+/// A response's body: fully-buffered `ResponseContent`, a streamed `ResponseBody`, or a completed
+/// WebSocket upgrade handshake handed off to a `WebSocketHandler`.
+#[derive(Debug)]
+enum Body {
+    Content(ResponseContent),
+    Streamed {
+        /// A full `Content-Type: ...\r\n` header, or `""` for none.
+        content_type: String,
+        body: Box<dyn ResponseBody>,
+    },
+    WebSocket {
+        /// The precomputed `Sec-WebSocket-Accept` handshake header value.
+        accept_key: String,
+        handler: Box<dyn WebSocketHandler>,
+    },
+}
+
 /// An http response.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct Response {
     /// The http status of the response.
     pub status: u16,
-    /// The content of the response.
-    pub content: ResponseContent,
+    body: Body,
 }
 
 impl Response {
@@ -1107,7 +2434,7 @@ impl Response {
     pub fn from_status(status: u16) -> Self {
         Self {
             status,
-            content: ResponseContent::None,
+            body: Body::Content(ResponseContent::None),
         }
     }
 
@@ -1116,7 +2443,7 @@ impl Response {
     pub fn from_message(status: u16, msg: String) -> Self {
         Self {
             status,
-            content: ResponseContent::Text(msg),
+            body: Body::Content(ResponseContent::Text(msg)),
         }
     }
 
@@ -1125,26 +2452,203 @@ impl Response {
     pub fn from_png(png: Vec<u8>) -> Self {
         Self {
             status: 200,
-            content: ResponseContent::Png(png),
+            body: Body::Content(ResponseContent::Png(png)),
+        }
+    }
+
+    /// Creates a new `Response` with a status code and content of a jpeg.
+    #[must_use]
+    pub fn from_jpeg(jpeg: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            body: Body::Content(ResponseContent::Jpeg(jpeg)),
         }
     }
 
-    /// Generates the http headers of this response (including ending blank line).
+    /// Creates a new `Response` with status `200` and content serialized from `value` as JSON.
+    ///
+    /// Requires the `json_response` feature.
+    #[cfg(feature = "json_response")]
+    pub fn from_json<T: Serialize>(value: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            status: 200,
+            body: Body::Content(ResponseContent::Json(serde_json::to_vec(value)?)),
+        })
+    }
+
+    /// Creates a new `Response` with a status code and a body read incrementally from `body`.
+    ///
+    /// `content_type` is a full `Content-Type: ...\r\n` header (or `""` for none)--unlike
+    /// `ResponseContent`, a streamed body has no fixed shape to infer it from. Since the body's
+    /// length isn't known up front, it's sent with `Transfer-Encoding: chunked` instead of a
+    /// `Content-Length`. Compression (see `Config::with_compression`) doesn't apply to streamed
+    /// bodies.
     #[must_use]
-    fn generate_headers(&self) -> String {
+    pub fn from_stream(
+        status: u16,
+        content_type: impl Into<String>,
+        body: impl ResponseBody + 'static,
+    ) -> Self {
+        Self {
+            status,
+            body: Body::Streamed {
+                content_type: content_type.into(),
+                body: Box::new(body),
+            },
+        }
+    }
+
+    /// Creates a `Response` that upgrades the connection to a WebSocket and hands each inbound
+    /// text/binary frame to `handler` for as long as the connection stays open.
+    ///
+    /// `client_key` is the client's `Sec-WebSocket-Key` header (see `Request::websocket_key`);
+    /// the request's nonce/secret must already have been validated exactly like any other request
+    /// before this is used to respond to it--the upgrade doesn't skip that.
+    #[must_use]
+    #[allow(dead_code)]
+    pub fn websocket(client_key: &str, handler: impl WebSocketHandler + 'static) -> Self {
+        Self {
+            status: 101,
+            body: Body::WebSocket {
+                accept_key: websocket::accept_key(client_key),
+                handler: Box::new(handler),
+            },
+        }
+    }
+
+    /// Creates a `Response` from an already-known status and content.
+    ///
+    /// Unlike `from_status`/`from_message`/`from_png`, this doesn't assume any particular status
+    /// for a given content--used by `crate::client` to reconstruct a `Response` read off the
+    /// wire, where the two are already paired up.
+    #[must_use]
+    pub(crate) fn from_parts(status: u16, content: ResponseContent) -> Self {
+        Self {
+            status,
+            body: Body::Content(content),
+        }
+    }
+
+    /// Generates the http headers of a response (including ending blank line) with status
+    /// `status`, `content_type` (a full `Content-Type: ...\r\n` header, or `""` for none), body
+    /// shape `body`, encoded with `encoding_token` (the `Content-Encoding` value) if given, and a
+    /// `Connection` header reflecting `keep_alive`.
+    #[must_use]
+    fn generate_headers(
+        status: u16,
+        content_type: &str,
+        body: BodyKind,
+        encoding_token: Option<&str>,
+        keep_alive: bool,
+    ) -> String {
+        let length_header = match body {
+            BodyKind::Empty => "Content-Length: 0\r\n".to_owned(),
+            BodyKind::Sized(len) => format!("Content-Length: {len}\r\n"),
+            BodyKind::Streamed => "Transfer-Encoding: chunked\r\n".to_owned(),
+        };
+        let connection_header = if keep_alive { "keep-alive" } else { "close" };
+
         format!(
-            "HTTP/1.1 {}\r\n{}Content-Length: \
-             {}\r\n\r\n",
-            self.status,
-            self.content.content_type_header_repr(),
-            self.content.len(),
+            "HTTP/1.1 {status}\r\n{content_type}{}{length_header}Connection: {connection_header}\r\n\r\n",
+            encoding_token.map_or_else(String::new, |token| format!("Content-Encoding: {token}\r\n")),
         )
     }
 
-    /// Writes the http of this response to a `TcpStream`.
-    fn write_to(&self, stream: &mut TcpStream) -> std::io::Result<()> {
-        stream.write_all(self.generate_headers().as_bytes())?;
-        stream.write_all(self.content.as_bytes())
+    /// Writes the http of this response to a connection.
+    ///
+    /// If `compression` is set and the content is eligible (see
+    /// `ResponseContent::is_compression_eligible`), the body is compressed with the best coding
+    /// mutually supported by this server and `accept_encoding` (the client's parsed
+    /// `Accept-Encoding` tokens)--if there's no such coding (including when `accept_encoding` is
+    /// empty), the body is written uncompressed instead. Streamed bodies are never compressed and
+    /// are written as chunks of `<hex-len>\r\n<bytes>\r\n`, terminated with `0\r\n\r\n`. `keep_alive`
+    /// sets the `Connection` header so the client knows whether to expect the connection to stay
+    /// open (it's ignored for a WebSocket upgrade, which sets its own `Connection: Upgrade`). A
+    /// WebSocket upgrade writes the `101` handshake, clears the connection's read timeout (the
+    /// frame loop is a persistent control channel, not a single request/response, and shouldn't be
+    /// torn down just because the client goes quiet between messages), and then blocks running the
+    /// frame loop (see `websocket::run`) until the connection closes.
+    fn write_to<S: Connection + ?Sized>(
+        &mut self,
+        stream: &mut S,
+        accept_encoding: &[String],
+        compression: Option<&CompressionConfig>,
+        keep_alive: bool,
+    ) -> std::io::Result<()> {
+        match &mut self.body {
+            Body::Content(content) => {
+                if let Some(config) =
+                    compression.filter(|config| content.is_compression_eligible(config))
+                {
+                    if let Some((body, token)) =
+                        compression::negotiate(content.as_bytes(), accept_encoding, config)
+                    {
+                        stream.write_all(
+                            Self::generate_headers(
+                                self.status,
+                                content.content_type_header_repr(),
+                                BodyKind::Sized(body.len()),
+                                Some(token),
+                                keep_alive,
+                            )
+                            .as_bytes(),
+                        )?;
+                        return stream.write_all(&body);
+                    }
+                }
+
+                stream.write_all(
+                    Self::generate_headers(
+                        self.status,
+                        content.content_type_header_repr(),
+                        content.body_kind(),
+                        None,
+                        keep_alive,
+                    )
+                    .as_bytes(),
+                )?;
+                stream.write_all(content.as_bytes())
+            }
+            Body::Streamed { content_type, body } => {
+                stream.write_all(
+                    Self::generate_headers(
+                        self.status,
+                        content_type,
+                        BodyKind::Streamed,
+                        None,
+                        keep_alive,
+                    )
+                    .as_bytes(),
+                )?;
+
+                while let Some(chunk) = body.next_chunk()? {
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    write!(stream, "{:x}\r\n", chunk.len())?;
+                    stream.write_all(&chunk)?;
+                    stream.write_all(b"\r\n")?;
+                }
+
+                stream.write_all(b"0\r\n\r\n")
+            }
+            Body::WebSocket { accept_key, handler } => {
+                stream.write_all(
+                    format!(
+                        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                         Connection: Upgrade\r\nSec-WebSocket-Accept: {accept_key}\r\n\r\n"
+                    )
+                    .as_bytes(),
+                )?;
+
+                // The request-handling read timeout would otherwise tear this connection down
+                // after 2s of client idle--fine for a request/response, not for a control channel
+                // that's supposed to stay open indefinitely between messages.
+                stream.set_timeouts(None)?;
+
+                websocket::run(stream, handler.as_mut())
+            }
+        }
     }
 }
 
@@ -1153,7 +2657,7 @@ mod tests {
     /// Generates the http of what a response should look like from status and content.
     fn format_http_response(status: u16, content: &str) -> String {
         format!(
-            "HTTP/1.1 {status}\r\n{}Content-Length: {}\r\n\r\n{content}",
+            "HTTP/1.1 {status}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{content}",
             if content.is_empty() {
                 ""
             } else {
@@ -1190,17 +2694,26 @@ mod tests {
             }
 
             fn send_request(&mut self, method: Method, path: &str) -> String {
+                self.send_request_with_body(method, path, b"")
+            }
+
+            fn send_request_with_body(&mut self, method: Method, path: &str, body: &[u8]) -> String {
                 // We sleep to make sure the nonce is different.
                 thread::sleep(Duration::from_millis(1));
                 let mut stream = std::net::TcpStream::connect(self.dst_addr).unwrap();
                 let nonce = Duration::since_unix_epoch().as_millis();
+                // `Connection: close` keeps this one-shot mock simple--it never reuses a
+                // connection, so there's no reason to have the server hold it open.
                 let http = format!(
-                    "{method} {path} HTTP/1.1\r\nContent-Length: 0\r\nNonce: {nonce}\r\nSecret: \
-                     {}\r\n\r\n",
+                    "{method} {path} HTTP/1.1\r\nContent-Length: {}\r\nVersion: {}\r\nNonce: \
+                     {nonce}\r\nSecret: {}\r\nConnection: close\r\n\r\n",
+                    body.len(),
+                    super::PROTOCOL_VERSION,
                     self.key.generate_secret(nonce)
                 );
 
                 stream.write_all(http.as_bytes()).unwrap();
+                stream.write_all(body).unwrap();
                 // The server writes the headers and content with two separate `write` calls, and if
                 // we read immediately without waiting first, we'll only get the first `write` call.
                 // TODO: should probably make this less of a horrible hack
@@ -1224,6 +2737,7 @@ mod tests {
             .clone()
             .build(DummyLogger::new())
             .expect("failed to build server from config");
+        let shutdown = server.shutdown_handle();
 
         let server_res = std::thread::spawn(move || {
             server
@@ -1231,14 +2745,24 @@ mod tests {
                     Request {
                         method: Method::Get,
                         path,
+                        ..
                     } if path == "/test1" => Response::from_status(200),
                     Request {
                         method: Method::Post,
                         path,
+                        ..
                     } if path == "/test2" => {
                         Response::from_message(400, "this is a message".to_owned())
                     }
-                    Request { method, path } => panic!("received {method} request to {path}"),
+                    Request {
+                        method: Method::Post,
+                        path,
+                        body,
+                        ..
+                    } if path == "/echo_body" => {
+                        Response::from_message(200, String::from_utf8_lossy(&body).into_owned())
+                    }
+                    Request { method, path, .. } => panic!("received {method} request to {path}"),
                 })
                 .unwrap();
         });
@@ -1251,6 +2775,10 @@ mod tests {
             client.send_request(Method::Post, "/test2"),
             format_http_response(400, "this is a message")
         );
+        assert_eq!(
+            client.send_request_with_body(Method::Post, "/echo_body", b"hello body"),
+            format_http_response(200, "hello body")
+        );
 
         // Test invalid key
         {
@@ -1262,16 +2790,83 @@ mod tests {
             client.key = config.key.unwrap();
         }
 
-        // Stop the server by sending it a request to an unexpected endpoint, causing it to panic.
-        client.send_request(Method::Get, "/stop");
+        // A request to an unexpected endpoint panics in the handler. The worker pool catches the
+        // panic so it doesn't take the rest of the server down with it--the connection is just
+        // closed without a response.
+        assert_eq!(client.send_request(Method::Get, "/unhandled"), "");
+
+        // The server is still serving other connections after the panic.
+        assert_eq!(
+            client.send_request(Method::Get, "/test1"),
+            format_http_response(200, "")
+        );
+
+        // Gracefully stop the server and make sure `run` actually returns cleanly.
+        shutdown.shutdown();
+        server_res.join().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::thread;
+        use std::time::Duration;
 
-        // Pull the value out of the threads panic and ignore it if it's our stop request, otherwise
-        // reraise it.
-        if let Err(e) = server_res.join() {
-            match e.downcast::<String>() {
-                Ok(s) if *s == "received GET request to /stop" => (),
-                i => panic!("{i:?}"),
+        use super::{Config, DummyLogger, DurationExt, Key, Response};
+
+        // See the note in `test_server` about why this isn't port 0.
+        let config = Config::new()
+            .on_localhost()
+            .with_port(35622)
+            .with_key(Key::new("this is a key and it's 32 bytes.").expect("invalid key"));
+        let key = config.key.clone().unwrap();
+        let addr = config.sock_addr().unwrap();
+
+        let server = config.build(DummyLogger::new()).expect("failed to build server from config");
+        let shutdown = server.shutdown_handle();
+
+        let server_res = thread::spawn(move || {
+            server.run(|_| Response::from_status(200)).unwrap();
+        });
+
+        let mut stream = loop {
+            if let Ok(stream) = TcpStream::connect(addr) {
+                break stream;
             }
-        }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        let mut send = |keep_alive: bool| {
+            // We sleep to make sure the nonce is different from one request to the next.
+            thread::sleep(Duration::from_millis(1));
+            let nonce = Duration::since_unix_epoch().as_millis();
+            let http = format!(
+                "GET /ping HTTP/1.1\r\nContent-Length: 0\r\nVersion: {}\r\nNonce: {nonce}\r\n\
+                 Secret: {}\r\n{}\r\n",
+                super::PROTOCOL_VERSION,
+                key.generate_secret(nonce),
+                if keep_alive { "" } else { "Connection: close\r\n" },
+            );
+            stream.write_all(http.as_bytes()).unwrap();
+            // Same hack as `ClientMock::send_request_with_body`--see the `TODO` there.
+            thread::sleep(Duration::from_millis(100));
+            let mut buf = [0u8; 4096];
+            let len = stream.read(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).to_string()
+        };
+
+        // Two requests served over the same connection, without ever reconnecting.
+        assert!(send(true).starts_with("HTTP/1.1 200\r\nContent-Length: 0\r\nConnection: keep-alive"));
+        assert!(send(true).starts_with("HTTP/1.1 200\r\nContent-Length: 0\r\nConnection: keep-alive"));
+
+        // `Connection: close` ends the loop server-side--the connection is closed after this
+        // response, so a further read gets eof.
+        assert!(send(false).starts_with("HTTP/1.1 200\r\nContent-Length: 0\r\nConnection: close"));
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+        shutdown.shutdown();
+        server_res.join().unwrap();
     }
 }
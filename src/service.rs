@@ -0,0 +1,208 @@
+//! Running as a Windows service: `--install`/`--uninstall` subcommands and the service control
+//! dispatcher.
+//!
+//! This lets the server survive logout and start at boot by registering with the Service Control
+//! Manager (SCM), instead of only running as a hidden-console foreground process.
+
+use std::ffi::OsStr;
+use std::os::windows::prelude::OsStrExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::{io, ptr};
+
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::shared::ntdef::LPWSTR;
+use winapi::shared::winerror::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_FAILED_SERVICE_CONTROLLER_CONNECT, NO_ERROR};
+use winapi::um::winnt::DELETE;
+use winapi::um::winsvc::{
+    self, CloseServiceHandle, CreateServiceW, DeleteService, OpenSCManagerW, OpenServiceW,
+    RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, SC_MANAGER_CONNECT,
+    SC_MANAGER_CREATE_SERVICE, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_ALL_ACCESS,
+    SERVICE_AUTO_START, SERVICE_ERROR_NORMAL, SERVICE_RUNNING, SERVICE_STATUS, SERVICE_STATUS_HANDLE,
+    SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+};
+
+use crate::server::{Logger, Request, Response, Server, ShutdownHandle};
+
+/// The name this service is registered with the SCM under, and looked back up by on uninstall.
+const SERVICE_NAME: &str = "RemoteControlService";
+
+/// The process creation flag child processes should be spawned with: there's no console to
+/// inherit while running as a service, and a stray one shouldn't be created.
+pub const CHILD_CREATION_FLAGS: DWORD = winapi::um::winbase::CREATE_NO_WINDOW;
+
+/// The `ShutdownHandle` of the `Server` currently running as this service, so `control_handler`
+/// (invoked directly by the SCM, with no way to pass it extra context) can reach it.
+static SHUTDOWN: OnceLock<ShutdownHandle> = OnceLock::new();
+
+/// The actual server loop to run once the service reports itself as running, boxed so
+/// `service_main` (a bare `extern "system" fn") can call it without any context of its own.
+static RUN: OnceLock<Box<dyn Fn() -> io::Result<()> + Send + Sync>> = OnceLock::new();
+
+/// The handle `control_handler` reports status changes through, set once by `service_main`.
+static STATUS_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+/// Encodes `s` as a null-terminated wide (UTF-16) string, as winapi's `*W` functions expect.
+fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+/// Registers this binary with the Service Control Manager as an auto-starting service.
+pub fn install() -> io::Result<()> {
+    let exe_path = to_wide(&std::env::current_exe()?.to_string_lossy());
+    let name = to_wide(SERVICE_NAME);
+
+    unsafe {
+        let scm = OpenSCManagerW(ptr::null(), ptr::null(), SC_MANAGER_CREATE_SERVICE);
+        if scm.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let service = CreateServiceW(
+            scm,
+            name.as_ptr(),
+            name.as_ptr(),
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            exe_path.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+        );
+        CloseServiceHandle(scm);
+
+        if service.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        CloseServiceHandle(service);
+    }
+
+    Ok(())
+}
+
+/// Marks this service for deletion with the Service Control Manager.
+///
+/// The SCM only actually removes it once the service is stopped and every open handle to it is
+/// closed.
+pub fn uninstall() -> io::Result<()> {
+    let name = to_wide(SERVICE_NAME);
+
+    unsafe {
+        let scm = OpenSCManagerW(ptr::null(), ptr::null(), SC_MANAGER_CONNECT);
+        if scm.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let service = OpenServiceW(scm, name.as_ptr(), DELETE);
+        CloseServiceHandle(scm);
+        if service.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let deleted = DeleteService(service);
+        CloseServiceHandle(service);
+        if deleted == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `server` as the `SERVICE_NAME` service, dispatching requests to `f`.
+///
+/// Blocks until the SCM stops the service (or, if this process wasn't actually launched by the
+/// SCM--e.g. running it directly from a terminal to test--falls back to just running `server` in
+/// the foreground).
+pub fn run<L>(server: Server<L>, f: impl Fn(Request) -> Response + Sync + Send + 'static) -> io::Result<()>
+where
+    L: Logger + Send + Sync + 'static,
+{
+    SHUTDOWN
+        .set(server.shutdown_handle())
+        .expect("service::run must only be called once");
+    RUN.set(Box::new(move || server.run(&f)))
+        .map_err(|_| ())
+        .expect("service::run must only be called once");
+
+    let name = to_wide(SERVICE_NAME);
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_ptr() as LPWSTR,
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: ptr::null_mut(),
+            lpServiceProc: None,
+        },
+    ];
+
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } != 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT as i32) {
+        // Not actually launched by the SCM--run the same logic directly in the foreground.
+        return RUN.get().unwrap()();
+    }
+    Err(err)
+}
+
+/// Reports this service's current status to the SCM, via the handle `service_main` registered.
+fn report_status(state: DWORD, controls_accepted: DWORD) {
+    let handle = STATUS_HANDLE.load(Ordering::SeqCst) as SERVICE_STATUS_HANDLE;
+    if handle.is_null() {
+        return;
+    }
+
+    let mut status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: controls_accepted,
+        dwWin32ExitCode: NO_ERROR,
+        dwServiceSpecificExitCode: 0,
+        dwCheckPoint: 0,
+        dwWaitHint: 0,
+    };
+    unsafe {
+        SetServiceStatus(handle, &mut status);
+    }
+}
+
+/// The service entry point the SCM calls once `StartServiceCtrlDispatcherW` connects.
+///
+/// Registers the control handler, reports running, runs the stored server loop to completion (it
+/// only returns once `control_handler` triggers a shutdown), then reports stopped.
+unsafe extern "system" fn service_main(_argc: DWORD, _argv: *mut LPWSTR) {
+    let name = to_wide(SERVICE_NAME);
+    let handle = unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), ptr::null_mut()) };
+    STATUS_HANDLE.store(handle as usize, Ordering::SeqCst);
+
+    report_status(SERVICE_RUNNING, SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN);
+
+    let run = RUN.get().expect("service_main invoked without service::run setting RUN up first");
+    let _ = run();
+
+    report_status(SERVICE_STOPPED, 0);
+}
+
+/// Handles control events the SCM sends this service, mapping a stop or shutdown request to a
+/// graceful shutdown of the running `Server` loop.
+unsafe extern "system" fn control_handler(control: DWORD, _event_type: DWORD, _event_data: LPVOID, _context: LPVOID) -> DWORD {
+    match control {
+        winsvc::SERVICE_CONTROL_STOP | winsvc::SERVICE_CONTROL_SHUTDOWN => {
+            report_status(SERVICE_STOP_PENDING, 0);
+            if let Some(shutdown) = SHUTDOWN.get() {
+                shutdown.shutdown();
+            }
+            NO_ERROR
+        }
+        winsvc::SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
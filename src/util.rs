@@ -2,68 +2,24 @@
 //!
 //! This module handles the actual actions of requests made to the server.
 
-use std::os::windows::prelude::OsStrExt;
-use std::{ffi::OsStr, fmt};
+use std::fmt;
+use std::io::{self, Read};
+use std::os::windows::io::AsRawHandle;
+use std::os::windows::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
 
-use screenshots::image::codecs::png;
-use winapi::shared::minwindef::{LPARAM, UINT, WPARAM};
-use winapi::{shared::windef::HWND, um::winuser};
+use screenshots::image::codecs::{jpeg, png};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess, TerminateProcess};
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::{INFINITE, WAIT_OBJECT_0, WAIT_TIMEOUT};
+use winapi::um::winnt::{HANDLE, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE, SYNCHRONIZE};
+use winapi::um::winuser;
 
-/// A handle. This might be null.
-struct Hwnd(HWND);
-
-impl Hwnd {
-    /// Returns `Some(self)` if the handle isn't null, and `None` if it is.
-    #[must_use]
-    fn some_non_null(self) -> Option<Self> {
-        (!self.0.is_null()).then_some(self)
-    }
-
-    /// Posts a message with this handle.
-    unsafe fn post_message(self, msg: UINT, w_param: WPARAM, l_param: LPARAM) -> i32 {
-        unsafe { winuser::PostMessageW(self.0, msg, w_param, l_param) }
-    }
-}
-
-/// An error indicated a needed process isn't currently running.
-#[derive(Debug, Clone)]
-pub struct ProcessNotRunningError {
-    /// The (friendly) process name.
-    process_name: &'static str,
-}
-
-impl ProcessNotRunningError {
-    /// Creates a new `ProcessNotRunningError`.
-    #[must_use]
-    pub const fn new(process_name: &'static str) -> Self {
-        Self { process_name }
-    }
-}
-
-impl fmt::Display for ProcessNotRunningError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} process isn't running", self.process_name)
-    }
-}
-
-/// Gets the window handle of the task bar, if it's running.
-fn get_taskbar_hwnd() -> Result<Hwnd, ProcessNotRunningError> {
-    let name: Vec<_> = OsStr::new("Shell_TrayWnd\0").encode_wide().collect();
-    unsafe {
-        Hwnd(winuser::FindWindowW(name.as_ptr(), std::ptr::null()))
-            .some_non_null()
-            .ok_or(ProcessNotRunningError::new("taskbar"))
-    }
-}
-
-/// Gets the window handle of the desktop, if it's running.
-fn get_desktop_window_hwnd() -> Result<Hwnd, ProcessNotRunningError> {
-    unsafe {
-        Hwnd(winuser::GetDesktopWindow())
-            .some_non_null()
-            .ok_or(ProcessNotRunningError::new("desktop window"))
-    }
-}
+use crate::server::ResponseBody;
+use crate::window::MessagePumpHandle;
 
 /// Puts the computer to sleep.
 ///
@@ -77,12 +33,10 @@ pub fn sleep_computer() -> bool {
 
 /// Puts the display to sleep.
 ///
-/// This will silently fail if there is no taskbar process running.
-pub fn sleep_display() -> Result<(), ProcessNotRunningError> {
-    // TODO: this doesn't work. might just be my computer.
-    get_desktop_window_hwnd().map(|hwnd| unsafe {
-        hwnd.post_message(winuser::WM_SYSCOMMAND, winuser::SC_MONITORPOWER, 2);
-    })
+/// Broadcasting the monitor-power-off command only works when issued from a thread that owns a
+/// window, so this is routed through `pump`'s message-pump thread rather than done directly.
+pub fn sleep_display(pump: &MessagePumpHandle) -> io::Result<()> {
+    pump.sleep_display()
 }
 
 /// Locks the screen.
@@ -107,11 +61,11 @@ impl fmt::Display for LockScreenError {
 
 /// Minimizes all open windows.
 ///
-/// This will silently fail if there is no taskbar process running.
-pub fn minimize_windows() -> Result<(), ProcessNotRunningError> {
-    get_taskbar_hwnd().map(|hwnd| unsafe {
-        hwnd.post_message(winuser::WM_COMMAND, 419, 0);
-    })
+/// Broadcast like this (rather than, say, posted straight to the taskbar) so it keeps working
+/// regardless of which windows exist; routed through `pump`'s message-pump thread, since
+/// broadcasting only works when issued from a thread that owns a window.
+pub fn minimize_windows(pump: &MessagePumpHandle) -> io::Result<()> {
+    pump.minimize_windows()
 }
 
 /// Take a screenshot of the primary display.
@@ -137,6 +91,81 @@ pub fn take_screenshot() -> Result<Vec<u8>, NoDisplayError> {
     Ok(png_buf)
 }
 
+/// The `multipart/x-mixed-replace` boundary marker `stream_screen`'s frames are separated by.
+///
+/// Pair this with `Response::from_stream`'s `content_type` argument, e.g.
+/// `format!("Content-Type: multipart/x-mixed-replace; boundary={STREAM_BOUNDARY}\r\n")`.
+pub const STREAM_BOUNDARY: &str = "frame";
+
+/// Captures and JPEG-encodes one frame of the primary display.
+///
+/// `quality` is the JPEG quality, 1-100 (higher is better-looking but larger).
+///
+/// Unlike `take_screenshot`, every failure here is reported through the `Result` rather than a
+/// `.expect()`--this is called from `ScreenStream::next_chunk`, which runs from inside
+/// `Response::write_to` rather than the handler, outside of `Server::run`'s `catch_unwind`. A mid-
+/// stream failure (the display locking while a client is still attached, say) would otherwise
+/// panic the worker thread instead of just ending the stream.
+fn capture_screen_jpeg(quality: u8) -> Result<Vec<u8>, NoDisplayError> {
+    let screens = screenshots::Screen::all().map_err(|_| NoDisplayError)?;
+    let primary_screen = screens.first().ok_or(NoDisplayError)?;
+    let bitmap = primary_screen.capture().map_err(|_| NoDisplayError)?;
+
+    let mut jpeg_buf = Vec::new();
+    bitmap
+        .write_with_encoder(jpeg::JpegEncoder::new_with_quality(&mut jpeg_buf, quality))
+        .map_err(|_| NoDisplayError)?;
+    Ok(jpeg_buf)
+}
+
+/// A live feed of the primary display, one JPEG frame at a time, for `stream_screen`.
+#[derive(Debug)]
+struct ScreenStream {
+    /// The minimum time to wait between frames, derived from the requested frame rate.
+    frame_interval: Duration,
+    /// The JPEG quality (1-100) frames are encoded at.
+    quality: u8,
+}
+
+impl ResponseBody for ScreenStream {
+    /// Waits out the frame interval, then captures, encodes, and wraps one frame as a
+    /// `multipart/x-mixed-replace` part.
+    ///
+    /// Never returns `Ok(None)`--the feed ends when writing a chunk fails (the client
+    /// disconnected) or when a frame can't be captured (this returns `Err`); either error ends
+    /// `Response::write_to`'s chunk loop.
+    fn next_chunk(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        std::thread::sleep(self.frame_interval);
+
+        let frame = capture_screen_jpeg(self.quality)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+        let mut chunk = format!(
+            "--{STREAM_BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            frame.len(),
+        )
+        .into_bytes();
+        chunk.extend_from_slice(&frame);
+        chunk.extend_from_slice(b"\r\n");
+
+        Ok(Some(chunk))
+    }
+}
+
+/// Streams the primary display as MJPEG (a `multipart/x-mixed-replace` body of JPEG frames) for
+/// live remote viewing, captured at `frame_rate` frames per second and encoded at `quality`
+/// (1-100).
+///
+/// Pass the result to `Response::from_stream`, with a `Content-Type` built from
+/// `STREAM_BOUNDARY`.
+#[must_use]
+pub fn stream_screen(frame_rate: u32, quality: u8) -> impl ResponseBody {
+    ScreenStream {
+        frame_interval: Duration::from_secs_f64(1.0 / f64::from(frame_rate.max(1))),
+        quality,
+    }
+}
+
 /// Occurs when a screenshot was attempted but there was no display.
 #[derive(Debug, Copy, Clone)]
 pub struct NoDisplayError;
@@ -151,3 +180,295 @@ impl From<NoDisplayError> for crate::Response {
         Self::from_message(400, value.to_string())
     }
 }
+
+/// The result of `run_process`: either the process ran to completion within the timeout, or it's
+/// still running when the timeout elapsed.
+pub enum RunOutcome {
+    /// The process exited before the timeout elapsed.
+    Exited {
+        /// The process's exit code.
+        exit_code: u32,
+        /// Everything the process wrote to stdout.
+        stdout: Vec<u8>,
+        /// Everything the process wrote to stderr.
+        stderr: Vec<u8>,
+    },
+    /// The timeout elapsed before the process exited. It's left running.
+    StillRunning {
+        /// The still-running process's id, to later `kill_process` or otherwise track it.
+        pid: u32,
+    },
+}
+
+/// Parses `/run`'s request body into its optional `Working-Dir`/`Timeout-Ms` headers and the
+/// command line, mirroring the header-block/blank-line/body shape of the request itself.
+///
+/// Both headers are optional; a body with no blank line at all is taken to be just the command
+/// line, with no headers.
+#[must_use]
+pub fn parse_run_request(body: &[u8]) -> (Option<String>, Option<u32>, String) {
+    let body = String::from_utf8_lossy(body);
+    let header_end = body
+        .find("\r\n\r\n")
+        .map(|i| (i, 4))
+        .or_else(|| body.find("\n\n").map(|i| (i, 2)));
+    let (headers, command_line) = match header_end {
+        Some((i, sep_len)) => (&body[..i], &body[i + sep_len..]),
+        None => ("", body.as_ref()),
+    };
+
+    let working_dir = headers
+        .lines()
+        .find_map(|l| l.strip_prefix("Working-Dir: "))
+        .map(str::to_owned);
+    let timeout_ms = headers
+        .lines()
+        .find_map(|l| l.strip_prefix("Timeout-Ms: "))
+        .and_then(|v| v.trim().parse().ok());
+
+    (working_dir, timeout_ms, command_line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Spawns `command_line` (run through `cmd /C`, so it supports the usual shell syntax), in
+/// `working_dir` if given (otherwise this process's own working directory), and waits up to
+/// `timeout_ms` (`None` waits indefinitely) for it to exit.
+///
+/// stdout/stderr are drained on their own threads for the whole wait, not just after it--a child
+/// that writes more than a pipe buffer's worth of output would otherwise block on write and never
+/// exit, hanging this wait forever. On timeout, the process is left running and its pid is
+/// returned so a later `kill_process` can stop it; the reader threads are left detached, quietly
+/// finishing (and having their output discarded) once the process eventually exits or is killed.
+pub fn run_process(
+    command_line: &str,
+    working_dir: Option<&str>,
+    timeout_ms: Option<u32>,
+) -> Result<RunOutcome, ProcessLaunchError> {
+    let mut command = Command::new("cmd");
+    command
+        .args(["/C", command_line])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .creation_flags(crate::service::CHILD_CREATION_FLAGS);
+    if let Some(dir) = working_dir {
+        command.current_dir(dir);
+    }
+
+    let mut child = command.spawn().map_err(ProcessLaunchError::new)?;
+    let pid = child.id();
+    let handle = child.as_raw_handle() as HANDLE;
+
+    let stdout_reader = child.stdout.take().map(spawn_pipe_reader);
+    let stderr_reader = child.stderr.take().map(spawn_pipe_reader);
+
+    // `child`'s `Drop` impl closes this handle for us once it goes out of scope--closing it here
+    // ourselves would make the later `GetExitCodeProcess` call (which needs it) unsound.
+    match unsafe { WaitForSingleObject(handle, timeout_ms.unwrap_or(INFINITE)) } {
+        WAIT_OBJECT_0 => {
+            let mut exit_code = 0;
+            unsafe {
+                GetExitCodeProcess(handle, &mut exit_code);
+            }
+
+            // The process has already exited, so its end of each pipe is closed and these joins
+            // only wait on however much is left for the reader thread to drain.
+            let stdout = stdout_reader.map_or_else(Vec::new, |r| r.join().unwrap_or_default());
+            let stderr = stderr_reader.map_or_else(Vec::new, |r| r.join().unwrap_or_default());
+
+            Ok(RunOutcome::Exited { exit_code, stdout, stderr })
+        }
+        WAIT_TIMEOUT => Ok(RunOutcome::StillRunning { pid }),
+        _ => Err(ProcessLaunchError::new(io::Error::last_os_error())),
+    }
+}
+
+/// Spawns a thread that reads `pipe` to completion, for draining a child's stdout/stderr
+/// concurrently with waiting on it (rather than only after, which risks deadlock on a full pipe
+/// buffer).
+fn spawn_pipe_reader(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// Terminates the process with `pid`.
+pub fn kill_process(pid: u32) -> Result<(), ProcessError> {
+    unsafe {
+        let handle = OpenProcess(
+            PROCESS_TERMINATE | PROCESS_QUERY_LIMITED_INFORMATION | SYNCHRONIZE,
+            0,
+            pid,
+        );
+        if handle.is_null() {
+            return Err(ProcessError::new(pid));
+        }
+
+        let terminated = TerminateProcess(handle, 1);
+        // Wait for the process to actually finish exiting (and thus fully reap it) before
+        // closing our handle, so we don't report success while it's still tearing down.
+        if terminated != 0 {
+            WaitForSingleObject(handle, INFINITE);
+        }
+        CloseHandle(handle);
+
+        if terminated == 0 {
+            return Err(ProcessError::new(pid));
+        }
+    }
+
+    Ok(())
+}
+
+/// Occurs when `run_process` fails to spawn or wait on the requested process.
+#[derive(Debug)]
+pub struct ProcessLaunchError(io::Error);
+impl ProcessLaunchError {
+    /// Creates a new `ProcessLaunchError` from the underlying OS error.
+    fn new(err: io::Error) -> Self {
+        Self(err)
+    }
+}
+impl std::error::Error for ProcessLaunchError {}
+impl fmt::Display for ProcessLaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to launch or wait on process: {}", self.0)
+    }
+}
+impl From<ProcessLaunchError> for crate::Response {
+    fn from(value: ProcessLaunchError) -> Self {
+        Self::from_message(500, value.to_string())
+    }
+}
+
+/// Occurs when `kill_process` couldn't open or terminate the process with the given pid.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessError {
+    /// The pid that couldn't be operated on.
+    pid: u32,
+}
+impl ProcessError {
+    /// Creates a new `ProcessError` for the process with `pid`.
+    #[must_use]
+    pub const fn new(pid: u32) -> Self {
+        Self { pid }
+    }
+}
+impl std::error::Error for ProcessError {}
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no running process with pid {} (or it couldn't be terminated)", self.pid)
+    }
+}
+impl From<ProcessError> for crate::Response {
+    fn from(value: ProcessError) -> Self {
+        Self::from_message(400, value.to_string())
+    }
+}
+
+/// Opens the default webcam, requesting the highest available frame rate.
+fn open_default_camera() -> Result<nokhwa::Camera, NoCameraError> {
+    let format =
+        nokhwa::utils::RequestedFormat::new::<nokhwa::pixel_format::RgbFormat>(
+            nokhwa::utils::RequestedFormatType::AbsoluteHighestFrameRate,
+        );
+    nokhwa::Camera::new(nokhwa::utils::CameraIndex::Index(0), format).map_err(|_| NoCameraError)
+}
+
+/// Captures one frame from the default webcam and JPEG-encodes it.
+///
+/// `quality` is the JPEG quality, 1-100 (higher is better-looking but larger).
+///
+/// Every failure here is reported through the `Result` rather than a `.expect()`--this is called
+/// from `CameraStream::next_chunk`, which runs from inside `Response::write_to` rather than the
+/// handler, outside of `Server::run`'s `catch_unwind`, so a panic here would take down a worker
+/// thread instead of just ending the stream.
+fn capture_camera_jpeg(camera: &mut nokhwa::Camera, quality: u8) -> Result<Vec<u8>, NoCameraError> {
+    let frame = camera.frame().map_err(|_| NoCameraError)?;
+    let image = frame
+        .decode_image::<nokhwa::pixel_format::RgbFormat>()
+        .map_err(|_| NoCameraError)?;
+
+    let mut jpeg_buf = Vec::new();
+    image
+        .write_with_encoder(jpeg::JpegEncoder::new_with_quality(&mut jpeg_buf, quality))
+        .map_err(|_| NoCameraError)?;
+    Ok(jpeg_buf)
+}
+
+/// Captures and JPEG-encodes a single frame from the default webcam.
+///
+/// Opens and closes the camera for this one frame, mirroring `take_screenshot`'s re-fetch-every-
+/// call simplicity.
+pub fn capture_camera(quality: u8) -> Result<Vec<u8>, NoCameraError> {
+    let mut camera = open_default_camera()?;
+    camera.open_stream().map_err(|_| NoCameraError)?;
+    capture_camera_jpeg(&mut camera, quality)
+}
+
+/// A live feed of the default webcam, one JPEG frame at a time, for `stream_camera`.
+///
+/// Unlike `ScreenStream`, this holds the camera open for the life of the stream--reopening a
+/// physical webcam device for every frame would be far too slow.
+struct CameraStream {
+    /// The open camera frames are read from.
+    camera: nokhwa::Camera,
+    /// The JPEG quality (1-100) frames are encoded at.
+    quality: u8,
+}
+
+impl fmt::Debug for CameraStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CameraStream")
+            .field("quality", &self.quality)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseBody for CameraStream {
+    /// Captures, encodes, and wraps one frame as a `multipart/x-mixed-replace` part.
+    ///
+    /// Never returns `Ok(None)`--the feed ends when writing a chunk fails (the client
+    /// disconnected) or when a frame can't be captured (this returns `Err`); either error ends
+    /// `Response::write_to`'s chunk loop.
+    fn next_chunk(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let frame = capture_camera_jpeg(&mut self.camera, self.quality)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+        let mut chunk = format!(
+            "--{STREAM_BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            frame.len(),
+        )
+        .into_bytes();
+        chunk.extend_from_slice(&frame);
+        chunk.extend_from_slice(b"\r\n");
+
+        Ok(Some(chunk))
+    }
+}
+
+/// Streams the default webcam as MJPEG (a `multipart/x-mixed-replace` body of JPEG frames) for
+/// live remote viewing, encoded at `quality` (1-100).
+///
+/// Pass the result to `Response::from_stream`, with a `Content-Type` built from
+/// `STREAM_BOUNDARY`.
+pub fn stream_camera(quality: u8) -> Result<impl ResponseBody, NoCameraError> {
+    let mut camera = open_default_camera()?;
+    camera.open_stream().map_err(|_| NoCameraError)?;
+    Ok(CameraStream { camera, quality })
+}
+
+/// Occurs when a webcam frame was requested but there was no camera, or it couldn't be opened.
+#[derive(Debug, Copy, Clone)]
+pub struct NoCameraError;
+impl std::error::Error for NoCameraError {}
+impl fmt::Display for NoCameraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no camera found (to capture)")
+    }
+}
+impl From<NoCameraError> for crate::Response {
+    fn from(value: NoCameraError) -> Self {
+        Self::from_message(400, value.to_string())
+    }
+}
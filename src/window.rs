@@ -0,0 +1,217 @@
+//! A hidden, message-only window and the thread that owns it.
+//!
+//! Some operations (broadcasting power/system commands) and notifications (power and session
+//! change events) are only delivered to--or only work when issued from--a thread that owns a
+//! window and is pumping its message queue. This module owns exactly one such window for the
+//! life of the program, and exposes a small handle request handlers can use to ask it to perform
+//! windowed operations.
+
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::windows::prelude::OsStrExt;
+use std::ptr;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::{
+    CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, PostMessageW, RegisterClassExW,
+    SendMessageTimeoutW, TranslateMessage, HWND_BROADCAST, HWND_MESSAGE, MSG, SC_MONITORPOWER, SMTO_ABORTIFHUNG,
+    WM_APP, WM_COMMAND, WM_POWERBROADCAST, WM_SYSCOMMAND, WM_WTSSESSION_CHANGE, WNDCLASSEXW,
+};
+use winapi::um::winuser::{WTSRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION};
+
+use crate::server::Logger;
+
+/// The window class this module's window is registered under.
+const CLASS_NAME: &str = "RemoteControlMessageWindow";
+
+/// The message asking the pump thread to broadcast the monitor-power-off command.
+const WM_APP_SLEEP_DISPLAY: UINT = WM_APP + 1;
+/// The message asking the pump thread to broadcast "minimize all windows" (the same command the
+/// taskbar's own "show desktop" button sends).
+const WM_APP_MINIMIZE_WINDOWS: UINT = WM_APP + 2;
+
+/// The logger system notifications (`WM_POWERBROADCAST`, `WM_WTSSESSION_CHANGE`) are surfaced
+/// through, set once by `spawn`.
+///
+/// `window_proc` is a bare `extern "system" fn`--the only way it can reach outside state is
+/// through statics like this one.
+static LOGGER: OnceLock<Box<dyn Logger + Send + Sync>> = OnceLock::new();
+
+/// A handle to the running message-pump thread, for asking it to perform windowed operations.
+///
+/// Cheaply cloneable--every clone asks the same underlying thread.
+#[derive(Debug, Clone, Copy)]
+pub struct MessagePumpHandle {
+    /// The pump thread's hidden window. `HWND` isn't `Send`, but the raw value underneath it is
+    /// just an opaque handle, safe to share across threads as long as it's only ever used to post
+    /// messages to it (not to manipulate any window state directly).
+    hwnd: isize,
+}
+
+impl MessagePumpHandle {
+    /// Asks the message-pump thread to broadcast the display-sleep command.
+    pub fn sleep_display(&self) -> io::Result<()> {
+        self.post(WM_APP_SLEEP_DISPLAY)
+    }
+
+    /// Asks the message-pump thread to broadcast the minimize-all-windows command.
+    pub fn minimize_windows(&self) -> io::Result<()> {
+        self.post(WM_APP_MINIMIZE_WINDOWS)
+    }
+
+    /// Posts `msg` to the pump thread's window.
+    fn post(&self, msg: UINT) -> io::Result<()> {
+        let ok = unsafe { PostMessageW(self.hwnd as HWND, msg, 0, 0) };
+        if ok == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Encodes `s` as a null-terminated wide (UTF-16) string, as winapi's `*W` functions expect.
+fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+/// Spawns the message-pump thread: registers a hidden, message-only window and runs its message
+/// loop for the life of the program, forwarding power/session-change notifications to `logger`.
+///
+/// Blocks until the window is created (or fails to be), then returns a handle to it.
+pub fn spawn<L: Logger + Send + Sync + 'static>(logger: L) -> io::Result<MessagePumpHandle> {
+    LOGGER.set(Box::new(logger)).ok();
+
+    let (ready_tx, ready_rx) = mpsc::sync_channel(0);
+    thread::Builder::new().name("message-pump".to_owned()).spawn(move || pump(&ready_tx))?;
+
+    ready_rx
+        .recv()
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "message-pump thread exited before creating its window",
+            ))
+        })
+        .map(|hwnd| MessagePumpHandle { hwnd })
+}
+
+/// The pump thread's body: creates the window, reports it (or a creation failure) through
+/// `ready`, then runs the standard `GetMessageW`/`TranslateMessage`/`DispatchMessageW` loop until
+/// the window is destroyed (which, in practice, is only at process exit).
+fn pump(ready: &mpsc::SyncSender<io::Result<isize>>) {
+    let hwnd = match create_window() {
+        Ok(hwnd) => hwnd,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    };
+
+    unsafe {
+        WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION);
+    }
+
+    let _ = ready.send(Ok(hwnd as isize));
+
+    let mut msg: MSG = unsafe { mem::zeroed() };
+    loop {
+        let got = unsafe { GetMessageW(&mut msg, ptr::null_mut(), 0, 0) };
+        if got <= 0 {
+            break;
+        }
+        unsafe {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+}
+
+/// Registers `CLASS_NAME` and creates a hidden, message-only (`HWND_MESSAGE`) window of it.
+fn create_window() -> io::Result<HWND> {
+    let class_name = to_wide(CLASS_NAME);
+
+    let class = WNDCLASSEXW {
+        cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+        style: 0,
+        lpfnWndProc: Some(window_proc),
+        cbClsExtra: 0,
+        cbWndExtra: 0,
+        hInstance: ptr::null_mut(),
+        hIcon: ptr::null_mut(),
+        hCursor: ptr::null_mut(),
+        hbrBackground: ptr::null_mut(),
+        lpszMenuName: ptr::null(),
+        lpszClassName: class_name.as_ptr(),
+        hIconSm: ptr::null_mut(),
+    };
+
+    if unsafe { RegisterClassExW(&class) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let hwnd = unsafe {
+        CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            ptr::null(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            HWND_MESSAGE,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+
+    if hwnd.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(hwnd)
+    }
+}
+
+/// Handles messages sent/posted to the pump thread's window.
+unsafe extern "system" fn window_proc(hwnd: HWND, msg: UINT, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+    match msg {
+        WM_APP_SLEEP_DISPLAY => {
+            broadcast(WM_SYSCOMMAND, SC_MONITORPOWER, 2);
+            0
+        }
+        WM_APP_MINIMIZE_WINDOWS => {
+            // `419` is the command id the taskbar's own "show desktop" button sends.
+            broadcast(WM_COMMAND, 419, 0);
+            0
+        }
+        WM_POWERBROADCAST => {
+            if let Some(logger) = LOGGER.get() {
+                logger.info(&format!("power broadcast event (wParam = {w_param})"));
+            }
+            1 // BROADCAST_QUERY_DENY's opposite: allow the operation to proceed
+        }
+        WM_WTSSESSION_CHANGE => {
+            if let Some(logger) = LOGGER.get() {
+                logger.info(&format!("session change event (wParam = {w_param})"));
+            }
+            0
+        }
+        _ => unsafe { DefWindowProcW(hwnd, msg, w_param, l_param) },
+    }
+}
+
+/// Broadcasts `msg`/`w_param`/`l_param` to every top-level window, aborting (rather than hanging
+/// this thread) if a receiving window is unresponsive.
+fn broadcast(msg: UINT, w_param: WPARAM, l_param: LPARAM) {
+    let mut result = 0;
+    unsafe {
+        SendMessageTimeoutW(HWND_BROADCAST, msg, w_param, l_param, SMTO_ABORTIFHUNG, 5000, &mut result);
+    }
+}